@@ -0,0 +1,584 @@
+// Unpacks downloaded archives in-process: tarballs (plain, gz, xz, zst, bz2), zips, .debs,
+// .rpms and single compressed files, told apart by their first bytes rather than their name.
+// Nothing in an archive gets to write outside the destination, entries with `..` or
+// absolute paths and symlinks pointing out of it are refused.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, Read},
+    os::unix::fs::{PermissionsExt, symlink},
+    path::{Component, Path, PathBuf},
+};
+
+pub mod ar;
+pub mod cpio;
+pub mod rpm;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Tar(Compression),
+    Zip,
+    // a single compressed file, e.g. foo.txt.gz
+    Compressed(Compression),
+    Deb,
+    Rpm,
+}
+
+// how far into a file anything below looks, a tar header is 512 bytes
+const SNIFF_LEN: usize = 512;
+
+impl Format {
+    // going by the file name, the same suffixes makepkg knows
+    pub fn from_name(name: &str) -> Option<Format> {
+        let name = name.to_lowercase();
+
+        let suffixes = [
+            (".tar", Format::Tar(Compression::None)),
+            (".tar.gz", Format::Tar(Compression::Gzip)),
+            (".tgz", Format::Tar(Compression::Gzip)),
+            (".tar.xz", Format::Tar(Compression::Xz)),
+            (".txz", Format::Tar(Compression::Xz)),
+            (".tar.zst", Format::Tar(Compression::Zstd)),
+            (".tzst", Format::Tar(Compression::Zstd)),
+            (".tar.bz2", Format::Tar(Compression::Bzip2)),
+            (".tbz2", Format::Tar(Compression::Bzip2)),
+            (".tbz", Format::Tar(Compression::Bzip2)),
+            (".zip", Format::Zip),
+            (".gz", Format::Compressed(Compression::Gzip)),
+            (".xz", Format::Compressed(Compression::Xz)),
+            (".zst", Format::Compressed(Compression::Zstd)),
+            (".bz2", Format::Compressed(Compression::Bzip2)),
+            (".deb", Format::Deb),
+            (".rpm", Format::Rpm),
+        ];
+
+        suffixes
+            .iter()
+            .find(|(suffix, _)| name.ends_with(suffix))
+            .map(|(_, format)| *format)
+    }
+
+    // going by the content, the name only decides what magic bytes can't
+    pub fn detect(file: &Path) -> io::Result<Option<Format>> {
+        let name = file
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let by_name = Format::from_name(&name);
+
+        let head = read_head(BufReader::new(File::open(file)?))?;
+
+        let compression = match head.as_slice() {
+            [0x1f, 0x8b, ..] => Compression::Gzip,
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Compression::Xz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Compression::Zstd,
+            [b'B', b'Z', b'h', ..] => Compression::Bzip2,
+            // an empty zip is only the end of central directory record
+            [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => {
+                return Ok(Some(Format::Zip));
+            }
+            _ if head.starts_with(ar::MAGIC) => {
+                // a .deb is an ar archive whose first member is debian-binary
+                return Ok(if head[8..].starts_with(b"debian-binary") {
+                    Some(Format::Deb)
+                } else {
+                    by_name
+                });
+            }
+            // the rpm lead
+            _ if head.starts_with(rpm::MAGIC) => return Ok(Some(Format::Rpm)),
+            _ if is_tar(&head) => return Ok(Some(Format::Tar(Compression::None))),
+            // pre-POSIX tarballs have no magic at all
+            _ => {
+                return Ok(by_name.filter(|format| *format == Format::Tar(Compression::None)));
+            }
+        };
+
+        // only decompressing the start tells a tarball from any other compressed file
+        let inner = read_head(decoder(file, compression)?)?;
+
+        if is_tar(&inner) || by_name == Some(Format::Tar(compression)) {
+            Ok(Some(Format::Tar(compression)))
+        } else {
+            Ok(Some(Format::Compressed(compression)))
+        }
+    }
+}
+
+fn read_head(reader: impl Read) -> io::Result<Vec<u8>> {
+    let mut head = vec![];
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+
+    Ok(head)
+}
+
+// "ustar\0" (POSIX) or "ustar  " (GNU) at offset 257 of the first header
+fn is_tar(head: &[u8]) -> bool {
+    head.len() == SNIFF_LEN && head[257..].starts_with(b"ustar")
+}
+
+// files that aren't archives are left alone
+pub fn unpack(file: &Path, destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let format = match Format::detect(file)? {
+        Some(format) => format,
+        None => return Ok(()),
+    };
+
+    println!("=> \x1b[33;1mTRY:\x1b[0m Extracting {name}..");
+
+    fs::create_dir_all(destination)?;
+
+    let result = match format {
+        Format::Tar(compression) => extract_tar(decoder(file, compression)?, destination, false),
+        Format::Zip => extract_zip(File::open(file)?, destination),
+        Format::Compressed(compression) => {
+            // foo.txt.gz -> foo.txt, without a suffix to drop it can't be named after itself
+            let output_name = match Format::from_name(&name) {
+                Some(Format::Compressed(_)) => Path::new(&name)
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+                _ => format!("{name}.out"),
+            };
+            let mut output = create(&destination.join(output_name))?;
+
+            io::copy(&mut decoder(file, compression)?, &mut output)?;
+            Ok(())
+        }
+        // like bsdtar, a .deb source just becomes its debian-binary, control and data tarballs
+        Format::Deb => ar::read_members(BufReader::new(File::open(file)?), |member, data| {
+            let mut output = create(&destination.join(enclosed(Path::new(member))?))?;
+
+            io::copy(data, &mut output)?;
+            Ok(())
+        }),
+        // and a .rpm its payload, like bsdtar
+        Format::Rpm => rpm::open(file).and_then(|(_, payload)| cpio::extract(payload, destination)),
+    };
+
+    result.map_err(|e| format!("failed to extract {name}: {e}").into())
+}
+
+fn decoder(file: &Path, compression: Compression) -> io::Result<Box<dyn Read>> {
+    decompress(File::open(file)?, compression)
+}
+
+pub fn decompress<'a>(
+    reader: impl Read + 'a,
+    compression: Compression,
+) -> io::Result<Box<dyn Read + 'a>> {
+    let reader = BufReader::new(reader);
+
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        // multi-member, concatenated streams are valid gzip/xz/bzip2 files
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(reader)),
+        Compression::Xz => Box::new(xz2::bufread::XzDecoder::new_multi_decoder(reader)),
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(reader)?),
+        Compression::Bzip2 => Box::new(bzip2::bufread::MultiBzDecoder::new(reader)),
+    })
+}
+
+// package payloads may link to absolute paths, those only resolve once merged into /,
+// and keep their setuid, setgid and sticky bits (chrome-sandbox, sudo..)
+pub fn extract_tar(
+    reader: impl Read,
+    destination: &Path,
+    payload: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_mtime(true);
+    archive.set_preserve_permissions(payload);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.to_path_buf();
+        let relative = enclosed(&path)?;
+
+        let entry_type = entry.header().entry_type();
+
+        if let Some(target) = entry.link_name()? {
+            if entry_type.is_symlink() && !(payload && target.is_absolute()) {
+                check_symlink(&relative, &target)?;
+            } else if entry_type.is_hard_link() {
+                // hard links name another entry of the archive
+                enclosed(&target)?;
+            }
+        }
+
+        // unpack_in also refuses to write through a parent that resolves outside
+        if !entry.unpack_in(destination)? {
+            return Err(format!("{} escapes the extraction directory", path.display()).into());
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_zip(file: File, destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+    let root = fs::canonicalize(destination)?;
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let relative = enclosed(Path::new(file.name()))?;
+        let path = destination.join(&relative);
+
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+
+            if !fs::canonicalize(parent)?.starts_with(&root) {
+                return Err(format!("{} escapes the extraction directory", file.name()).into());
+            }
+        }
+
+        if file.is_symlink() {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+
+            check_symlink(&relative, Path::new(&target))?;
+            remove_existing(&path)?;
+            symlink(&target, &path)?;
+            continue;
+        }
+
+        let mut output = create(&path)?;
+        io::copy(&mut file, &mut output)?;
+
+        if let Some(mode) = file.unix_mode() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode & 0o777))?;
+        }
+    }
+
+    Ok(())
+}
+
+// the archive path as a plain relative path, or an error if it could point anywhere else
+fn enclosed(path: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let mut relative = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(format!("{} escapes the extraction directory", path.display()).into());
+            }
+        }
+    }
+
+    Ok(relative)
+}
+
+// a symlink at `link` (relative to the destination) may only point somewhere inside it
+fn check_symlink(link: &Path, target: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let escapes = || format!("{} -> {} points outside", link.display(), target.display());
+
+    if target.is_absolute() {
+        return Err(escapes().into());
+    }
+
+    let mut resolved: Vec<Component> = link
+        .parent()
+        .map(|parent| parent.components().collect())
+        .unwrap_or_default();
+
+    for component in target.components() {
+        match component {
+            Component::Normal(_) => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if resolved.pop().is_none() {
+                    return Err(escapes().into());
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return Err(escapes().into()),
+        }
+    }
+
+    Ok(())
+}
+
+// never write through whatever symlink might already be sitting at `path`
+fn create(path: &Path) -> io::Result<File> {
+    remove_existing(path)?;
+
+    File::create(path)
+}
+
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match path.symlink_metadata() {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use tar::{EntryType, Header};
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    // a fresh `dest` inside its own scratch dir, so writes that escape it land next to it
+    fn scratch(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("uvi-archive-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let destination = dir.join("dest");
+        fs::create_dir_all(&destination).unwrap();
+
+        (dir, destination)
+    }
+
+    // tar::Builder refuses `..` and absolute names, so the header fields are filled in by hand
+    fn tar(entries: &[(&str, EntryType, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+
+        for (name, kind, content) in entries {
+            let mut header = Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(*kind);
+            header.set_mode(0o644);
+
+            let data = if kind.is_symlink() || kind.is_hard_link() {
+                header.as_old_mut().linkname[..content.len()].copy_from_slice(content.as_bytes());
+                ""
+            } else {
+                content
+            };
+
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data.as_bytes()).unwrap();
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn zip(dir: &Path, entries: &[(&str, Option<&str>, &str)]) -> PathBuf {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+
+        for (name, link, content) in entries {
+            match link {
+                Some(target) => writer
+                    .add_symlink(*name, *target, SimpleFileOptions::default())
+                    .unwrap(),
+                None => {
+                    writer
+                        .start_file(*name, SimpleFileOptions::default())
+                        .unwrap();
+                    writer.write_all(content.as_bytes()).unwrap();
+                }
+            }
+        }
+
+        let path = dir.join("test.zip");
+        fs::write(&path, writer.finish().unwrap().into_inner()).unwrap();
+
+        path
+    }
+
+    fn extract(bytes: &[u8], destination: &Path, payload: bool) -> Result<(), String> {
+        extract_tar(Cursor::new(bytes), destination, payload).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn tar_extracts_files_and_inner_links() {
+        let (dir, destination) = scratch("tar");
+
+        let bytes = tar(&[
+            ("pkg/", EntryType::Directory, ""),
+            ("pkg/file", EntryType::Regular, "hello\n"),
+            ("pkg/link", EntryType::Symlink, "file"),
+            ("pkg/sub/up", EntryType::Symlink, "../file"),
+            ("pkg/hard", EntryType::Link, "pkg/file"),
+        ]);
+
+        extract(&bytes, &destination, false).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(destination.join("pkg/link")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("pkg/sub/up")).unwrap(),
+            "hello\n"
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("pkg/hard")).unwrap(),
+            "hello\n"
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tar_refuses_parent_and_absolute_paths() {
+        let (dir, destination) = scratch("tar-paths");
+
+        let parent = tar(&[("../evil", EntryType::Regular, "x")]);
+        assert!(extract(&parent, &destination, false).is_err());
+        assert!(!dir.join("evil").exists());
+
+        let nested = tar(&[("pkg/../../evil", EntryType::Regular, "x")]);
+        assert!(extract(&nested, &destination, true).is_err());
+        assert!(!dir.join("evil").exists());
+
+        let absolute = dir.join("absolute");
+        let absolute = tar(&[(absolute.to_str().unwrap(), EntryType::Regular, "x")]);
+        assert!(extract(&absolute, &destination, true).is_err());
+        assert!(!dir.join("absolute").exists());
+
+        let hard = tar(&[("hard", EntryType::Link, "../outside")]);
+        assert!(extract(&hard, &destination, false).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tar_refuses_escaping_symlinks() {
+        let (dir, destination) = scratch("tar-links");
+
+        for target in ["../outside", "a/../../outside", "/etc"] {
+            let bytes = tar(&[("link", EntryType::Symlink, target)]);
+
+            assert!(extract(&bytes, &destination, false).is_err(), "{target}");
+            assert!(destination.join("link").symlink_metadata().is_err());
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn tar_never_writes_through_a_symlink() {
+        let (dir, destination) = scratch("tar-through");
+        let outside = dir.join("outside");
+        fs::create_dir(&outside).unwrap();
+
+        // package payloads may link to absolute paths, but nothing can be written through them
+        let bytes = tar(&[
+            ("etc", EntryType::Symlink, outside.to_str().unwrap()),
+            ("etc/owned", EntryType::Regular, "x"),
+        ]);
+
+        assert!(extract(&bytes, &destination, true).is_err());
+        assert!(!outside.join("owned").exists());
+
+        // nor through one that was already there
+        let bytes = tar(&[("etc/owned", EntryType::Regular, "x")]);
+
+        assert!(extract(&bytes, &destination, true).is_err());
+        assert!(!outside.join("owned").exists());
+
+        // a file replaces a symlink instead of writing to its target
+        fs::write(outside.join("passwd"), "root").unwrap();
+        symlink(outside.join("passwd"), destination.join("passwd")).unwrap();
+
+        let bytes = tar(&[("passwd", EntryType::Regular, "replaced")]);
+        extract(&bytes, &destination, true).unwrap();
+
+        assert_eq!(fs::read_to_string(outside.join("passwd")).unwrap(), "root");
+        assert!(!destination.join("passwd").is_symlink());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn zip_refuses_escapes() {
+        let (dir, destination) = scratch("zip");
+        let outside = dir.join("outside");
+        fs::create_dir(&outside).unwrap();
+
+        let fine = zip(
+            &dir,
+            &[("a/file", None, "hello"), ("a/link", Some("file"), "")],
+        );
+        extract_zip(File::open(&fine).unwrap(), &destination).unwrap();
+        assert_eq!(
+            fs::read_to_string(destination.join("a/link")).unwrap(),
+            "hello"
+        );
+
+        for entries in [
+            vec![("../evil", None, "x")],
+            vec![("/evil", None, "x")],
+            vec![("link", Some("../outside"), "")],
+            vec![("link", Some("/etc"), "")],
+        ] {
+            let path = zip(&dir, &entries);
+
+            assert!(extract_zip(File::open(&path).unwrap(), &destination).is_err());
+        }
+
+        assert!(!dir.join("evil").exists());
+
+        // a symlink already sitting in the destination is never followed
+        symlink(&outside, destination.join("out")).unwrap();
+
+        let through = zip(&dir, &[("out/owned", None, "x")]);
+        assert!(extract_zip(File::open(&through).unwrap(), &destination).is_err());
+        assert!(!outside.join("owned").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn detect_by_content() {
+        let (dir, _) = scratch("detect");
+        let bytes = tar(&[("file", EntryType::Regular, "hello")]);
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&bytes).unwrap();
+
+        // misleading names, the content wins
+        let cases = [
+            ("plain.zip", bytes.clone(), Format::Tar(Compression::None)),
+            (
+                "download.tmp",
+                encoder.finish().unwrap(),
+                Format::Tar(Compression::Gzip),
+            ),
+            (
+                "notes.txt.xz",
+                zstd::encode_all(&b"hi"[..], 0).unwrap(),
+                Format::Compressed(Compression::Zstd),
+            ),
+        ];
+
+        for (name, content, format) in cases {
+            fs::write(dir.join(name), content).unwrap();
+
+            assert_eq!(
+                Format::detect(&dir.join(name)).unwrap(),
+                Some(format),
+                "{name}"
+            );
+        }
+
+        fs::write(dir.join("readme.md"), "# not an archive").unwrap();
+        assert_eq!(Format::detect(&dir.join("readme.md")).unwrap(), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
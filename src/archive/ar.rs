@@ -0,0 +1,87 @@
+// Reader for the Unix `ar` container .deb files are built on: an 8 byte magic, then each
+// member as a 60 byte text header followed by its data, padded to an even length.
+
+use std::io::{self, Read};
+
+pub const MAGIC: &[u8] = b"!<arch>\n";
+
+const HEADER_LEN: usize = 60;
+
+// calls `visit` with the name and contents of every member, in order
+pub fn read_members(
+    mut reader: impl Read,
+    mut visit: impl FnMut(&str, &mut dyn Read) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+
+    if magic != MAGIC {
+        return Err("not an ar archive".into());
+    }
+
+    loop {
+        let mut header = [0u8; HEADER_LEN];
+
+        match read_full(&mut reader, &mut header)? {
+            0 => return Ok(()),
+            HEADER_LEN => {}
+            _ => return Err("truncated ar member header".into()),
+        }
+
+        if &header[58..60] != b"`\n" {
+            return Err("corrupt ar member header".into());
+        }
+
+        let mut size: u64 = field(&header[48..58])
+            .parse()
+            .map_err(|_| "corrupt ar member size")?;
+        let mut name = field(&header[0..16]).to_string();
+
+        // BSD ar keeps long names in front of the data: #1/<length of the name>
+        if let Some(length) = name.strip_prefix("#1/") {
+            let length: u64 = length.parse().map_err(|_| "corrupt ar member name")?;
+            let mut long_name = vec![0u8; length as usize];
+
+            reader.read_exact(&mut long_name)?;
+            size = size.checked_sub(length).ok_or("corrupt ar member size")?;
+            name = String::from_utf8_lossy(&long_name)
+                .trim_end_matches('\0')
+                .to_string();
+        }
+
+        // GNU ar ends names with a `/`
+        let name = name.trim_end_matches('/');
+
+        let mut member = (&mut reader).take(size);
+        visit(name, &mut member)?;
+
+        // whatever `visit` didn't read still has to be skipped
+        io::copy(&mut member, &mut io::sink())?;
+
+        if member.limit() > 0 {
+            return Err(format!("ar member {name} is truncated").into());
+        }
+
+        if size % 2 == 1 {
+            io::copy(&mut (&mut reader).take(1), &mut io::sink())?;
+        }
+    }
+}
+
+fn field(bytes: &[u8]) -> &str {
+    std::str::from_utf8(bytes).unwrap_or_default().trim_end()
+}
+
+// like read_exact, but a clean end of file before the first byte isn't an error
+fn read_full(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 => break,
+            read => filled += read,
+        }
+    }
+
+    Ok(filled)
+}
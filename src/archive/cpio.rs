@@ -0,0 +1,262 @@
+// Reader for the "newc" cpio format rpm payloads use: a 110 byte ascii header per entry,
+// then the name and the data, each padded to 4 bytes, up to a TRAILER!!! entry.
+// Only used for package payloads, so symlinks to absolute paths are let through and
+// setuid, setgid and sticky bits are kept.
+
+use super::{check_symlink, create, enclosed, remove_existing};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read},
+    os::unix::fs::{PermissionsExt, symlink},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+const HEADER_LEN: u64 = 110;
+
+const TYPE_MASK: u64 = 0o170000;
+const DIRECTORY: u64 = 0o040000;
+const REGULAR: u64 = 0o100000;
+const SYMLINK: u64 = 0o120000;
+
+pub fn extract(
+    mut reader: impl Read,
+    destination: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let root = fs::canonicalize(destination)?;
+
+    // newc only stores the data of a hard linked file once, with the last of its names
+    let mut links: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+
+    loop {
+        let mut header = [0u8; HEADER_LEN as usize];
+        reader.read_exact(&mut header)?;
+
+        // 070702 is the same with a checksum nobody fills in
+        if &header[..6] != b"070701" && &header[..6] != b"070702" {
+            return Err("not a newc cpio archive".into());
+        }
+
+        let field = |index: usize| -> Result<u64, Box<dyn std::error::Error>> {
+            let hex = std::str::from_utf8(&header[6 + index * 8..14 + index * 8])?;
+
+            Ok(u64::from_str_radix(hex, 16)?)
+        };
+
+        let inode = field(0)?;
+        let mode = field(1)?;
+        let nlink = field(4)?;
+        let mtime = field(5)?;
+        let size = field(6)?;
+        let name_size = field(11)?;
+
+        let mut name = vec![0u8; name_size as usize];
+        reader.read_exact(&mut name)?;
+        skip(&mut reader, padding(HEADER_LEN + name_size))?;
+
+        let name = String::from_utf8_lossy(&name)
+            .trim_end_matches('\0')
+            .to_string();
+
+        if name == "TRAILER!!!" {
+            break;
+        }
+
+        let mut data = (&mut reader).take(size);
+        let relative = enclosed(Path::new(&name))?;
+        let path = destination.join(&relative);
+
+        if relative.as_os_str().is_empty() {
+            io::copy(&mut data, &mut io::sink())?;
+        } else if mode & TYPE_MASK == DIRECTORY {
+            fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+
+                if !fs::canonicalize(parent)?.starts_with(&root) {
+                    return Err(format!("{name} escapes the extraction directory").into());
+                }
+            }
+
+            match mode & TYPE_MASK {
+                REGULAR if nlink > 1 && size == 0 => {
+                    links.entry(inode).or_default().push(path);
+                }
+                REGULAR => {
+                    let mut output = create(&path)?;
+                    io::copy(&mut data, &mut output)?;
+
+                    output.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(mode as u32 & 0o7777))?;
+
+                    for link in links.remove(&inode).unwrap_or_default() {
+                        remove_existing(&link)?;
+                        fs::hard_link(&path, &link)?;
+                    }
+                }
+                SYMLINK => {
+                    let mut target = String::new();
+                    data.read_to_string(&mut target)?;
+
+                    if !Path::new(&target).is_absolute() {
+                        check_symlink(&relative, Path::new(&target))?;
+                    }
+
+                    remove_existing(&path)?;
+                    symlink(&target, &path)?;
+                }
+                _ => {
+                    println!(
+                        "=> \x1b[1mINFO:\x1b[0m Skipping {name}, device files and fifos aren't installed"
+                    );
+                }
+            }
+        }
+
+        io::copy(&mut data, &mut io::sink())?;
+
+        if data.limit() > 0 {
+            return Err(format!("cpio entry {name} is truncated").into());
+        }
+
+        skip(&mut reader, padding(size))?;
+    }
+
+    // names of a hard linked file whose data never came, it was empty
+    for link in links.into_values().flatten() {
+        create(&link)?;
+    }
+
+    Ok(())
+}
+
+fn padding(length: u64) -> u64 {
+    (4 - length % 4) % 4
+}
+
+fn skip(reader: &mut impl Read, length: u64) -> io::Result<()> {
+    io::copy(&mut reader.take(length), &mut io::sink())?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (inode, mode, nlink, name, data)
+    fn newc(entries: &[(u64, u64, u64, &str, &str)]) -> Vec<u8> {
+        let mut bytes = vec![];
+        let trailer = (0, 0, 1, "TRAILER!!!", "");
+
+        for (inode, mode, nlink, name, data) in entries.iter().chain([&trailer]) {
+            let fields = [
+                *inode,
+                *mode,
+                0,
+                0,
+                *nlink,
+                0,
+                data.len() as u64,
+                0,
+                0,
+                0,
+                0,
+                name.len() as u64 + 1,
+                0,
+            ];
+
+            bytes.extend(b"070701");
+            for field in fields {
+                bytes.extend(format!("{field:08x}").as_bytes());
+            }
+
+            bytes.extend(name.as_bytes());
+            bytes.push(0);
+            bytes.resize(
+                bytes.len() + padding(HEADER_LEN + name.len() as u64 + 1) as usize,
+                0,
+            );
+
+            bytes.extend(data.as_bytes());
+            bytes.resize(bytes.len() + padding(data.len() as u64) as usize, 0);
+        }
+
+        bytes
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uvi-cpio-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("dest")).unwrap();
+
+        dir
+    }
+
+    #[test]
+    fn extracts_payload_with_special_bits_and_hard_links() {
+        let dir = scratch("payload");
+        let destination = dir.join("dest");
+
+        let payload = newc(&[
+            (1, DIRECTORY | 0o755, 2, "./usr/lib", ""),
+            (
+                2,
+                REGULAR | 0o4755,
+                1,
+                "./usr/lib/chrome-sandbox",
+                "sandbox",
+            ),
+            (3, REGULAR | 0o2755, 1, "./usr/bin/locate", "locate"),
+            // newc only stores the data with the last name of a hard link
+            (4, REGULAR | 0o755, 2, "./usr/bin/gunzip", ""),
+            (4, REGULAR | 0o755, 2, "./usr/bin/gzip", "gzip"),
+            (5, SYMLINK | 0o777, 1, "./usr/bin/zcat", "/usr/bin/gzip"),
+        ]);
+
+        extract(&payload[..], &destination).unwrap();
+
+        let metadata = |path: &str| fs::symlink_metadata(destination.join(path)).unwrap();
+
+        assert_eq!(
+            metadata("usr/lib/chrome-sandbox").permissions().mode() & 0o7777,
+            0o4755
+        );
+        assert_eq!(
+            metadata("usr/bin/locate").permissions().mode() & 0o7777,
+            0o2755
+        );
+        assert_eq!(
+            fs::read_to_string(destination.join("usr/bin/gunzip")).unwrap(),
+            "gzip"
+        );
+        assert_eq!(
+            fs::read_link(destination.join("usr/bin/zcat")).unwrap(),
+            Path::new("/usr/bin/gzip")
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_escapes() {
+        let dir = scratch("escape");
+        let destination = dir.join("dest");
+
+        for entries in [
+            vec![(1, REGULAR | 0o644, 1, "../escape", "x")],
+            vec![(1, SYMLINK | 0o777, 1, "./link", "../../escape")],
+            vec![
+                (1, SYMLINK | 0o777, 1, "./etc", dir.to_str().unwrap()),
+                (2, REGULAR | 0o644, 1, "./etc/escape", "x"),
+            ],
+        ] {
+            assert!(extract(&newc(&entries)[..], &destination).is_err());
+            assert!(!dir.join("escape").exists());
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
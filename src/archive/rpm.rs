@@ -0,0 +1,389 @@
+// The rpm container: a 96 byte lead, a signature header padded to 8 bytes, the package
+// header, then the compressed cpio payload. Both headers are an index of tagged entries
+// pointing into a data store that follows it.
+
+use super::{Compression, decompress};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+
+pub const MAGIC: &[u8] = &[0xed, 0xab, 0xee, 0xdb];
+
+const HEADER_MAGIC: &[u8] = &[0x8e, 0xad, 0xe8, 0x01];
+const LEAD_LEN: usize = 96;
+
+// same limits rpm itself puts on a header
+const MAX_ENTRIES: u32 = 0x10000;
+const MAX_STORE: u32 = 256 * 1024 * 1024;
+
+pub const NAME: u32 = 1000;
+pub const VERSION: u32 = 1001;
+pub const RELEASE: u32 = 1002;
+pub const EPOCH: u32 = 1003;
+pub const PREIN: u32 = 1023;
+pub const POSTIN: u32 = 1024;
+pub const PREUN: u32 = 1025;
+pub const POSTUN: u32 = 1026;
+pub const OLDFILENAMES: u32 = 1027;
+pub const FILEFLAGS: u32 = 1037;
+pub const PROVIDENAME: u32 = 1047;
+pub const REQUIREFLAGS: u32 = 1048;
+pub const REQUIRENAME: u32 = 1049;
+pub const REQUIREVERSION: u32 = 1050;
+pub const PREINPROG: u32 = 1085;
+pub const POSTINPROG: u32 = 1086;
+pub const DIRINDEXES: u32 = 1116;
+pub const BASENAMES: u32 = 1117;
+pub const DIRNAMES: u32 = 1118;
+pub const PAYLOADFORMAT: u32 = 1124;
+pub const PAYLOADCOMPRESSOR: u32 = 1125;
+
+// the entry types uvi reads
+const INT8: u32 = 2;
+const INT16: u32 = 3;
+const INT32: u32 = 4;
+const INT64: u32 = 5;
+const STRING: u32 = 6;
+const STRING_ARRAY: u32 = 8;
+const I18NSTRING: u32 = 9;
+
+struct Entry {
+    kind: u32,
+    offset: usize,
+    count: usize,
+}
+
+pub struct Header {
+    index: HashMap<u32, Entry>,
+    store: Vec<u8>,
+}
+
+impl Header {
+    // returns the header and how many bytes it took up
+    fn read(reader: &mut impl Read) -> Result<(Header, usize), Box<dyn std::error::Error>> {
+        let mut intro = [0u8; 16];
+        reader.read_exact(&mut intro)?;
+
+        if &intro[..4] != HEADER_MAGIC {
+            return Err("corrupt rpm header".into());
+        }
+
+        let entries = u32::from_be_bytes(intro[8..12].try_into()?);
+        let store_len = u32::from_be_bytes(intro[12..16].try_into()?);
+
+        if entries > MAX_ENTRIES || store_len > MAX_STORE {
+            return Err("rpm header is too large".into());
+        }
+
+        let mut raw_index = vec![0u8; entries as usize * 16];
+        reader.read_exact(&mut raw_index)?;
+
+        let mut store = vec![0u8; store_len as usize];
+        reader.read_exact(&mut store)?;
+
+        let mut index = HashMap::new();
+
+        for raw in raw_index.chunks(16) {
+            let value =
+                |at: usize| u32::from_be_bytes([raw[at], raw[at + 1], raw[at + 2], raw[at + 3]]);
+
+            index.insert(
+                value(0),
+                Entry {
+                    kind: value(4),
+                    offset: value(8) as usize,
+                    count: value(12) as usize,
+                },
+            );
+        }
+
+        let length = 16 + raw_index.len() + store.len();
+
+        Ok((Header { index, store }, length))
+    }
+
+    // a STRING, or the first value of an array of them
+    pub fn string(&self, tag: u32) -> Option<String> {
+        self.strings(tag).into_iter().next()
+    }
+
+    pub fn strings(&self, tag: u32) -> Vec<String> {
+        let entry = match self.index.get(&tag) {
+            Some(entry) if [STRING, STRING_ARRAY, I18NSTRING].contains(&entry.kind) => entry,
+            _ => return vec![],
+        };
+
+        // a STRING's count is always 1
+        self.store
+            .get(entry.offset..)
+            .unwrap_or_default()
+            .split(|byte| *byte == 0)
+            .take(entry.count)
+            .map(|value| String::from_utf8_lossy(value).to_string())
+            .collect()
+    }
+
+    pub fn numbers(&self, tag: u32) -> Vec<u64> {
+        let entry = match self.index.get(&tag) {
+            Some(entry) => entry,
+            None => return vec![],
+        };
+
+        let width = match entry.kind {
+            INT8 => 1,
+            INT16 => 2,
+            INT32 => 4,
+            INT64 => 8,
+            _ => return vec![],
+        };
+
+        let end = entry
+            .offset
+            .saturating_add(entry.count.saturating_mul(width));
+
+        self.store
+            .get(entry.offset..end)
+            .unwrap_or_default()
+            .chunks(width)
+            .map(|bytes| {
+                bytes
+                    .iter()
+                    .fold(0, |value, byte| value << 8 | *byte as u64)
+            })
+            .collect()
+    }
+}
+
+// reads everything up to the payload, which comes back decompressed and ready for cpio
+pub fn open(file: &Path) -> Result<(Header, Box<dyn Read>), Box<dyn std::error::Error>> {
+    let mut reader = BufReader::new(File::open(file)?);
+
+    let mut lead = [0u8; LEAD_LEN];
+    reader.read_exact(&mut lead)?;
+
+    if !lead.starts_with(MAGIC) {
+        return Err(format!("{} is not an rpm", file.display()).into());
+    }
+
+    // digests and signatures, nothing uvi checks
+    let (_, signature_len) = Header::read(&mut reader)?;
+    let mut padding = vec![0u8; (8 - signature_len % 8) % 8];
+    reader.read_exact(&mut padding)?;
+
+    let (header, _) = Header::read(&mut reader)?;
+
+    if header
+        .string(PAYLOADFORMAT)
+        .is_some_and(|format| format != "cpio")
+    {
+        return Err(format!("{} doesn't have a cpio payload", file.display()).into());
+    }
+
+    // no compressor tag means gzip, the rpm default from before the tag existed
+    let compression = match header.string(PAYLOADCOMPRESSOR).as_deref() {
+        None | Some("gzip") => Compression::Gzip,
+        Some("xz") => Compression::Xz,
+        Some("zstd") => Compression::Zstd,
+        Some("bzip2") => Compression::Bzip2,
+        Some(other) => {
+            return Err(format!("{other} compressed rpm payloads aren't supported").into());
+        }
+    };
+
+    let payload = decompress(reader, compression)?;
+
+    Ok((header, payload))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::{fs, io::Write, path::PathBuf};
+
+    pub enum Value<'a> {
+        String(&'a str),
+        Strings(&'a [&'a str]),
+        Int32(&'a [u32]),
+    }
+
+    // a header the way rpm writes one: intro, index, then the data store
+    pub fn header(entries: &[(u32, Value)]) -> Vec<u8> {
+        let mut index: Vec<u8> = vec![];
+        let mut store: Vec<u8> = vec![];
+
+        for (tag, value) in entries {
+            let (kind, count) = match value {
+                Value::String(_) => (STRING, 1),
+                Value::Strings(values) => (STRING_ARRAY, values.len()),
+                Value::Int32(values) => (INT32, values.len()),
+            };
+
+            // numbers sit on their natural alignment
+            if kind == INT32 {
+                store.resize(store.len().next_multiple_of(4), 0);
+            }
+
+            for field in [*tag, kind, store.len() as u32, count as u32] {
+                index.extend(field.to_be_bytes());
+            }
+
+            match value {
+                Value::String(value) => {
+                    store.extend(value.as_bytes());
+                    store.push(0);
+                }
+                Value::Strings(values) => {
+                    for value in *values {
+                        store.extend(value.as_bytes());
+                        store.push(0);
+                    }
+                }
+                Value::Int32(values) => {
+                    for value in *values {
+                        store.extend(value.to_be_bytes());
+                    }
+                }
+            }
+        }
+
+        let mut out = HEADER_MAGIC.to_vec();
+        out.extend([0; 4]);
+        out.extend((entries.len() as u32).to_be_bytes());
+        out.extend((store.len() as u32).to_be_bytes());
+        out.extend(index);
+        out.extend(store);
+
+        out
+    }
+
+    // lead, a signature header padded to 8 bytes, the main header and the payload
+    pub fn package(main: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend([3, 0]);
+        out.resize(LEAD_LEN, 0);
+
+        // 269 is RPMSIGTAG_SHA1, 41 bytes of store so the padding isn't a no-op
+        out.extend(header(&[(269, Value::String(&"0".repeat(40)))]));
+        out.resize(out.len().next_multiple_of(8), 0);
+
+        out.extend(main);
+        out.extend(payload);
+
+        out
+    }
+
+    pub fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uvi-rpm-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn compress(data: &[u8], compressor: &str) -> Vec<u8> {
+        match compressor {
+            "gzip" => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            "xz" => {
+                let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            "zstd" => zstd::encode_all(data, 0).unwrap(),
+            "bzip2" => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn headers_and_payload() {
+        let dir = scratch("open");
+        let cpio = b"070701 not really a cpio archive";
+
+        for compressor in ["gzip", "xz", "zstd", "bzip2"] {
+            let main = header(&[
+                (NAME, Value::String("hello")),
+                (VERSION, Value::String("2.12.1")),
+                (RELEASE, Value::String("3.fc41")),
+                (EPOCH, Value::Int32(&[1])),
+                (BASENAMES, Value::Strings(&["hello", "hello.1.gz"])),
+                (PAYLOADFORMAT, Value::String("cpio")),
+                (PAYLOADCOMPRESSOR, Value::String(compressor)),
+            ]);
+            let file = dir.join(format!("hello-{compressor}.rpm"));
+
+            fs::write(&file, package(&main, &compress(cpio, compressor))).unwrap();
+
+            let (header, mut payload) = open(&file).unwrap();
+            let mut unpacked = vec![];
+            payload.read_to_end(&mut unpacked).unwrap();
+
+            assert_eq!(unpacked, cpio, "{compressor} payload");
+            assert_eq!(header.string(NAME).as_deref(), Some("hello"));
+            assert_eq!(header.numbers(EPOCH), [1]);
+            assert_eq!(header.strings(BASENAMES), ["hello", "hello.1.gz"]);
+            // not a string, so no string comes back
+            assert_eq!(header.string(EPOCH), None);
+        }
+
+        // before PAYLOADCOMPRESSOR existed the payload was always gzip
+        let main = header(&[(NAME, Value::String("old"))]);
+        fs::write(dir.join("old.rpm"), package(&main, &compress(cpio, "gzip"))).unwrap();
+
+        let (_, mut payload) = open(&dir.join("old.rpm")).unwrap();
+        let mut unpacked = vec![];
+        payload.read_to_end(&mut unpacked).unwrap();
+
+        assert_eq!(unpacked, cpio);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refused_packages() {
+        let dir = scratch("refused");
+        let error = |name: &str, content: Vec<u8>| {
+            let file = dir.join(name);
+            fs::write(&file, content).unwrap();
+
+            match open(&file) {
+                Ok(_) => panic!("{name} was opened"),
+                Err(e) => e.to_string(),
+            }
+        };
+
+        let main = header(&[(NAME, Value::String("hello"))]);
+
+        let mut lead = package(&main, b"");
+        lead[0] = 0xee;
+        assert!(error("lead.rpm", lead).ends_with("lead.rpm is not an rpm"));
+
+        let mut corrupt = package(&main, b"");
+        let at = corrupt.len() - main.len();
+        corrupt[at] = 0;
+        assert_eq!(error("header.rpm", corrupt), "corrupt rpm header");
+
+        let lzma = header(&[(PAYLOADCOMPRESSOR, Value::String("lzma"))]);
+        assert_eq!(
+            error("lzma.rpm", package(&lzma, b"")),
+            "lzma compressed rpm payloads aren't supported"
+        );
+
+        let drpm = header(&[(PAYLOADFORMAT, Value::String("drpm"))]);
+        assert!(error("delta.rpm", package(&drpm, b"")).ends_with("doesn't have a cpio payload"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+pub mod cmake;
 pub mod make;
 pub mod meson;
 pub mod pkgbuild;
@@ -0,0 +1,65 @@
+// Out-of-tree CMake builds: configure into the build dir, build with Ninja when it's
+// installed (plain Makefiles otherwise), then `cmake --install` into the staging dir.
+
+use crate::sandbox;
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+pub fn build(project_dir: &str, build_dir: &str, arguments: &str, destdir: &str) {
+    run_configure(project_dir, build_dir, arguments);
+    run_build(project_dir, build_dir);
+    run_install(project_dir, build_dir, destdir);
+}
+
+fn run_configure(project_dir: &str, build_dir: &str, arguments: &str) {
+    let mut args = vec!["-S", ".", "-B", build_dir];
+
+    // the generator can't change once the build dir is configured
+    if !is_configured(build_dir) && has_ninja() {
+        args.extend(["-G", "Ninja"]);
+    }
+
+    // a --bargs -DCMAKE_BUILD_TYPE=.. comes later and wins
+    args.push("-DCMAKE_BUILD_TYPE=Release");
+    args.extend(arguments.split_whitespace());
+
+    sandbox::run(project_dir, &[project_dir], "cmake", &args)
+        .expect("=>\x1b[31m ERR: failed to run cmake");
+}
+
+fn run_build(project_dir: &str, build_dir: &str) {
+    sandbox::run(
+        project_dir,
+        &[project_dir],
+        "cmake",
+        &["--build", build_dir, "--parallel"],
+    )
+    .expect("=>\x1b[31m ERR: failed to run cmake [build]");
+}
+
+fn run_install(project_dir: &str, build_dir: &str, destdir: &str) {
+    let destdir_arg = format!("DESTDIR={destdir}");
+
+    sandbox::run(
+        project_dir,
+        &[project_dir, destdir],
+        "env",
+        &[&destdir_arg, "cmake", "--install", build_dir],
+    )
+    .expect("=>\x1b[31m ERR: failed to run cmake [install]");
+}
+
+fn is_configured(build_dir: &str) -> bool {
+    Path::new(build_dir).join("CMakeCache.txt").exists()
+}
+
+fn has_ninja() -> bool {
+    Command::new("ninja")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
@@ -1,21 +1,27 @@
-use std::process::{Command, Stdio};
+use crate::sandbox;
+use std::process::Stdio;
 
-pub fn build(project_dir: &str, build_dir: &str, arguments: &str) {
-    install(project_dir, build_dir, arguments);
+pub fn build(project_dir: &str, build_dir: &str, arguments: &str, destdir: &str) {
+    install(project_dir, build_dir, arguments, destdir);
 }
 
-fn install(project_dir: &str, _build_dir: &str, _user_args: &str) {
-    let args = vec!["--prefix=/usr"];
+fn install(project_dir: &str, _build_dir: &str, arguments: &str, destdir: &str) {
+    // --prefix=.. and whatever --bargs holds, the prefix is what gets recorded
+    let args: Vec<&str> = arguments.split_whitespace().collect();
+    let destdir_arg = format!("DESTDIR={destdir}");
 
-    run_command(project_dir, "./configure", &args);
-    run_command(project_dir, "make", &vec!["-m"]);
-    run_command(project_dir, "make", &vec!["install"]);
+    run_command(project_dir, &[project_dir], "./configure", &args);
+    run_command(project_dir, &[project_dir], "make", &["-m"]);
+    run_command(
+        project_dir,
+        &[project_dir, destdir],
+        "make",
+        &[&destdir_arg, "install"],
+    );
 }
 
-fn run_command(dir: &str, name: &str, args: &[&str]) {
-    let status = Command::new(name)
-        .current_dir(dir)
-        .args(args)
+fn run_command(dir: &str, writable: &[&str], name: &str, args: &[&str]) {
+    let status = sandbox::command(dir, writable, name, args)
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
         .status()
@@ -27,3 +33,42 @@ fn run_command(dir: &str, name: &str, args: &[&str]) {
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::PermissionsExt};
+
+    #[test]
+    fn configure_gets_the_prefix_and_bargs() {
+        let dir = std::env::temp_dir().join(format!("uvi-make-prefix-{}", std::process::id()));
+        let (project, destdir) = (dir.join("project"), dir.join("destdir"));
+
+        fs::create_dir_all(&project).unwrap();
+        fs::write(
+            project.join("configure"),
+            "#!/bin/sh\necho \"$@\" > configured\n",
+        )
+        .unwrap();
+        fs::set_permissions(project.join("configure"), fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(
+            project.join("Makefile"),
+            "all:\ninstall:\n\tmkdir -p $(DESTDIR) && cp configured $(DESTDIR)/\n",
+        )
+        .unwrap();
+
+        build(
+            &project.to_string_lossy(),
+            &project.join("build").to_string_lossy(),
+            "--prefix=/opt/x --disable-nls",
+            &destdir.to_string_lossy(),
+        );
+
+        assert_eq!(
+            fs::read_to_string(destdir.join("configured")).unwrap(),
+            "--prefix=/opt/x --disable-nls\n"
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,21 @@
 // Edited version of the meson cargo package.
 
-use crate::run_command;
-use std::path::PathBuf;
+use crate::{dependencies::Dependency, sandbox};
+use serde_json::Value;
+use std::{path::PathBuf, process::Stdio};
 
-pub fn build(project_dir: &str, build_dir: &str, arguments: &str) {
+pub fn build(project_dir: &str, build_dir: &str, arguments: &str, destdir: &str) {
     run_meson(project_dir, build_dir, arguments);
-    run_ninja(project_dir, build_dir);
+    run_ninja(project_dir, build_dir, destdir);
 }
 
-fn run_ninja(project_dir: &str, build_dir: &str) {
+fn run_ninja(project_dir: &str, build_dir: &str, destdir: &str) {
     let args = vec!["-C", build_dir];
-    let install_args = vec!["-C", build_dir, "install"];
-    run_command(project_dir, "ninja", &args).expect("=>\x1b[31m ERR: failed to run ninja");
-    run_command(project_dir, "ninja", &install_args)
-        .expect("=>\x1b[31m ERR: failed to run ninja [install]");
+    let install_args = vec!["install", "-C", build_dir, "--destdir", destdir];
+    sandbox::run(project_dir, &[project_dir], "ninja", &args)
+        .expect("=>\x1b[31m ERR: failed to run ninja");
+    sandbox::run(project_dir, &[project_dir, destdir], "meson", &install_args)
+        .expect("=>\x1b[31m ERR: failed to run meson [install]");
 }
 
 fn run_meson(lib: &str, dir: &str, arguments: &str) {
@@ -23,35 +25,80 @@ fn run_meson(lib: &str, dir: &str, arguments: &str) {
 
         args.extend(extra_args);
 
-        run_command(lib, "meson", &args).expect("=>\x1b[31m ERR: failed to run meson");
+        sandbox::run(lib, &[lib], "meson", &args).expect("=>\x1b[31m ERR: failed to run meson");
     }
 }
 
-// fn get_deps(project_dir: &str) -> Vec<String> {
-//     let meson_path = Path::new(project_dir).join("meson.build");
+// meson's own lookups that don't go through pkg-config
+const BUILTIN_DEPENDENCIES: [&str; 7] = [
+    "threads",
+    "dl",
+    "openmp",
+    "intl",
+    "iconv",
+    "appleframeworks",
+    "blocks",
+];
 
-//     let content = match fs::read_to_string(&meson_path) {
-//         Ok(c) => c,
-//         Err(_) => {
-//             eprintln!("Could not read meson.build at {:?}", meson_path);
-//             return vec![];
-//         }
-//     };
+// what meson.build asks for according to `meson introspect --scan-dependencies`, minus
+// optional ones, ones inside an if and ones with a subproject fallback
+pub fn dependencies(project_dir: &str) -> Vec<Dependency> {
+    let output = sandbox::command(
+        project_dir,
+        &[],
+        "meson",
+        &["introspect", "--scan-dependencies", "meson.build"],
+    )
+    .stderr(Stdio::null())
+    .output();
 
-//     let re = Regex::new(r#"dependency\s*\(\s*['"]([^'"]+)['"]"#).unwrap();
+    let scanned: Value = match output {
+        Ok(output) if output.status.success() => {
+            serde_json::from_slice(&output.stdout).unwrap_or_default()
+        }
+        _ => {
+            println!("=> \x1b[31;1mERR:\x1b[0m meson couldn't scan meson.build for dependencies..");
+            return vec![];
+        }
+    };
 
-//     let mut deps: Vec<String> = re
-//         .captures_iter(&content)
-//         .map(|cap| cap[1].to_string())
-//         .collect();
+    let mut dependencies: Vec<Dependency> = vec![];
 
-//     deps.sort();
-//     deps.dedup();
-//     deps
-// }
+    for entry in scanned.as_array().into_iter().flatten() {
+        let name = match entry["name"].as_str() {
+            Some(name) if !name.is_empty() && !BUILTIN_DEPENDENCIES.contains(&name) => name,
+            _ => continue,
+        };
+
+        let required = entry["required"].as_bool().unwrap_or(true);
+        let conditional = entry["conditional"].as_bool().unwrap_or(false);
+        let has_fallback = entry["has_fallback"].as_bool().unwrap_or(false);
+
+        if !required || conditional || has_fallback {
+            continue;
+        }
+
+        let versions: Vec<&str> = entry["version"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|version| version.as_str())
+            .collect();
+
+        if versions.is_empty() {
+            dependencies.push(Dependency::pkg_config(name, None));
+        } else {
+            for version in versions {
+                dependencies.push(Dependency::pkg_config(name, Some(version)));
+            }
+        }
+    }
+
+    dependencies
+}
 
 fn is_configured(dir: &str) -> bool {
     let mut path = PathBuf::from(dir);
     path.push("build.ninja");
-    return path.as_path().exists();
+    path.as_path().exists()
 }
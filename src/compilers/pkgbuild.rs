@@ -1,269 +1,300 @@
-// TODO: also stop redefining variables / maybe const would work here?
-// TODO: new parse function that allows for newlines / cmake_options=(1 \n 2)
-// TODO: try out fakeroot stuff maybe
-
-use crate::{download, fetch_env, run_command};
+pub mod checksums;
+pub mod evaluate;
+pub mod parser;
+pub mod signatures;
+pub mod sources;
+
+use crate::{database::Package, privilege, sandbox, staging};
+use evaluate::evaluate;
+use parser::{ParseResult, parse};
 use regex::Regex;
 use std::{
-    collections::HashMap,
     fs,
-    path::{Path, PathBuf},
+    path::Path,
+    process::{Command, Stdio},
 };
 
-#[derive(Debug, Default)]
-struct ParseResult {
-    url: Option<String>,
-    variables: HashMap<String, String>,
-    functions: HashMap<String, String>,
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub nocheck: bool,
+    pub noprepare: bool,
+    pub skip_integrity: bool,
+    pub skip_pgp: bool,
+    pub gpg_home: Option<String>,
 }
 
-pub fn build(src_dir: &Path) {
+pub fn build(
+    src_dir: &Path,
+    options: &Options,
+) -> Result<Vec<Package>, Box<dyn std::error::Error>> {
     let pkgbuild_path = src_dir.join("PKGBUILD");
 
-    make(&pkgbuild_path, &src_dir);
+    // package() runs under fakeroot, find out now rather than after the whole build
+    if !privilege::is_root() && !has_fakeroot() {
+        return Err("building PKGBUILDs needs fakeroot installed (it's in base-devel)".into());
+    }
+
+    let installed = make(&pkgbuild_path, src_dir, options)?;
 
     println!("=> \x1b[32;1mSUC:\x1b[0m Finished installing package!");
+
+    Ok(installed)
+}
+
+// fetches every source and lays them out in $srcdir
+fn download_pkgbuild(
+    result: &ParseResult,
+    src_dir: &Path,
+    srcdir: &Path,
+    options: &Options,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let sources = sources::sources(result);
+
+    println!(
+        "=> \x1b[1mINFO:\x1b[0m Retrieving {} sources..",
+        sources.len()
+    );
+
+    let fetched = sources::fetch_all(&sources, src_dir)?;
+
+    if options.skip_integrity {
+        println!("=> \x1b[1mINFO:\x1b[0m Skipping all source file integrity checks..");
+    } else {
+        checksums::verify(result, &sources, &fetched)?;
+
+        if options.skip_pgp {
+            println!(
+                "=> \x1b[1mINFO:\x1b[0m Skipping verification of source file PGP signatures.."
+            );
+        } else {
+            signatures::verify(result, &sources, &fetched, options.gpg_home.as_deref())?;
+        }
+    }
+
+    sources::extract_all(&sources, src_dir, srcdir, &result.array("noextract"))?;
+
+    Ok(())
 }
 
-fn parse(content: &str) -> ParseResult {
-    let mut result = ParseResult::default();
+// sources the PKGBUILD and calls one of its functions as the invoking user, like makepkg does.
+// package() goes through fakeroot so files in $pkgdir can be chowned, the owners it
+// hands out are saved to `fakeroot_state` and applied when merging
+fn run_function(
+    src_dir_str: &str,
+    name: &str,
+    srcdir: &Path,
+    pkgdir: &Path,
+    fakeroot_state: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let script = function_script(name);
+    let env = function_env(src_dir_str, srcdir, pkgdir);
+
+    let mut args: Vec<&str> = env.iter().map(|s| s.as_str()).collect();
+    args.extend(["bash", "-c", &script]);
+
+    // everything but $pkgdir lives in the PKGBUILD dir
+    let pkgdir_str = pkgdir.to_string_lossy();
+    let writable = [src_dir_str, &pkgdir_str];
+
+    if let Some(state) = fakeroot_state
+        && !privilege::is_root()
+    {
+        // the owners package() sets only live in fakeroot, -s keeps them for the merge
+        let state = state.to_string_lossy();
+        let mut fakeroot_args = vec!["-s", &state, "--", "env"];
+        fakeroot_args.extend(args);
+
+        sandbox::run(src_dir_str, &writable, "fakeroot", &fakeroot_args)
+    } else {
+        sandbox::run(src_dir_str, &writable, "env", &args)
+    }
+}
+
+fn has_fakeroot() -> bool {
+    Command::new("fakeroot")
+        .arg("-v")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn function_script(name: &str) -> String {
+    format!("source ./PKGBUILD\nset -e\ncd \"$srcdir\"\n{name}")
+}
 
-    let url_re =
-        Regex::new(r#"(?s)source=\(\s*["']?(?:(?P<alias>[^"'\s)]+)::)?(?P<url>[^"'\s)]+)"#)
-            .unwrap();
+fn function_env(src_dir_str: &str, srcdir: &Path, pkgdir: &Path) -> Vec<String> {
+    vec![
+        format!("startdir={src_dir_str}"),
+        format!("srcdir={}", srcdir.to_string_lossy()),
+        format!("pkgdir={}", pkgdir.to_string_lossy()),
+        format!("CARCH={}", std::env::consts::ARCH),
+    ]
+}
+
+// runs pkgver() and writes the new version back into the PKGBUILD, like makepkg does
+fn update_pkgver(
+    pkgbuild_path: &Path,
+    src_dir_str: &str,
+    srcdir: &Path,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let env = function_env(src_dir_str, srcdir, Path::new(""));
+
+    let script = function_script("pkgver");
 
-    if let Some(caps) = url_re.captures(content) {
-        result.url = Some(caps["url"].to_string());
+    let mut args: Vec<&str> = env.iter().map(|s| s.as_str()).collect();
+    args.extend(["bash", "-c", &script]);
+
+    let output = sandbox::command(src_dir_str, &[src_dir_str], "env", &args)
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    if !output.status.success() {
+        return Err("pkgver() failed".into());
     }
 
-    let kv_re = Regex::new(r#"(?m)^(?P<key>\w+)=["']?(?P<value>[^"'\n#]+)["']?"#).unwrap(); // god i hate regex
-    for caps in kv_re.captures_iter(content) {
-        let key = caps["key"].to_string();
-        let value = caps["value"].trim().to_string();
-        result.variables.insert(key, value); //TODO: allow for newlines
+    let new_pkgver = String::from_utf8_lossy(&output.stdout).trim().to_string();
+
+    if new_pkgver.is_empty() || new_pkgver.contains(['-', ':', '/', ' ', '\n']) {
+        return Err(format!("pkgver() returned an invalid version: {new_pkgver:?}").into());
     }
 
-    let func_re = Regex::new(r"(?m)^(?P<name>\w+)\s*\(\s*\)\s*\{(?P<body>(?s).*?)\n\}").unwrap();
-    for caps in func_re.captures_iter(content) {
-        let name = caps["name"].to_string();
-        let body = caps["body"].trim().to_string();
-        result.functions.insert(name, body);
+    let content = fs::read_to_string(pkgbuild_path)?;
+    let pkgver_re = Regex::new(r"(?m)^pkgver=\S*")?;
+    let pkgrel_re = Regex::new(r"(?m)^pkgrel=\S*")?;
+
+    if pkgver_re
+        .find(&content)
+        .is_none_or(|m| m.as_str() == format!("pkgver={new_pkgver}"))
+    {
+        return Ok(false);
     }
 
-    result
+    println!("=> \x1b[1mINFO:\x1b[0m Updated version: {new_pkgver}");
+
+    let content = pkgver_re.replace(&content, format!("pkgver={new_pkgver}").as_str());
+    let content = pkgrel_re.replace(&content, "pkgrel=1");
+
+    fs::write(pkgbuild_path, content.as_ref())?;
+
+    Ok(true)
 }
 
-fn download_pkgbuild(
-    pkg_fn_name: &str,
-    url: &str,
-    content: &str,
-    src_dir_str: &str,
-    pkgname: &str,
-) {
-    let result: ParseResult = parse(&content);
-
-    // let pkg_error: String = format!("echo '=> \x1b[1mINFO:\x1b[0m No prepare() function.'");
-
-    let pkg_fn: &str = result.functions.get(pkg_fn_name).unwrap().as_str();
-    let build_fn: &str = result.functions.get("build").unwrap().as_str();
-    // let prepare_fn: &str = result
-    //     .functions
-    //     .get("prepare")
-    //     .unwrap_or(&pkg_error)
-    //     .as_str();
-    let pkgbase: &str = result
-        .variables
-        .get("pkgbase")
-        .map(|s| s.as_str())
-        .unwrap_or("null");
-
-    let formatted_url: String =
-        format_pkgbuild(url, &content, src_dir_str, pkgname).replace("$pkgbase", pkgbase); // TODO: FUCK THIS
-    let formatted_pkg_fn: String = format_pkgbuild(pkg_fn, &content, src_dir_str, pkgname);
-    let formatted_build_fn: String = format_pkgbuild(build_fn, &content, src_dir_str, pkgname);
-    // let formatted_prepare_fn = format_pkgbuild(prepare_fn, &content, src_dir_str, pkgname);
-    // TODO: run_commmand with this panics??
-
-    let formatted_name: &str = formatted_url.rsplit_once("/").unwrap().1;
-    let formatted_path: PathBuf = Path::new(src_dir_str).join(formatted_name);
-
-    let home_path = fetch_env("HOME");
-    let user_name = home_path
-        .to_str()
-        .expect("/tmp")
-        .rsplit_once("/")
-        .unwrap()
-        .1;
-    println!("{user_name}");
-    let formatted_user_name = format!("{user_name}:{user_name}");
-
-    match download(&formatted_url, &formatted_path) {
-        Ok(_meow) => {
-            // run_command(src_dir_str, "bash", &["-c", &formatted_prepare_fn]).expect("oopsies");
-            println!("=> \x1b[32;1mSUC:\x1b[0m Running build() function!");
-            match run_command(src_dir_str, "sudo", &["bash", "-c", &formatted_build_fn]) {
-                // TODO maybe dont do sudo
-                Ok(_) => {
-                    run_command(
-                        src_dir_str,
-                        "sudo",
-                        &[
-                            "chown",
-                            "-Rv",
-                            &formatted_user_name,
-                            formatted_path.to_str().unwrap(), // TODO
-                        ],
-                    )
-                    .expect("Owned by root"); // TODO
-                    println!("=> \x1b[32;1mSUC:\x1b[0m Running package() function!");
-                    run_command(src_dir_str, "sudo", &["bash", "-c", &formatted_pkg_fn])
-                        .expect("=> \x1b[31;1mERR: Failed to run command..");
-                }
-                Err(_e) => println!("Err"),
-            };
+pub fn load(pkgbuild_path: &Path) -> Result<ParseResult, Box<dyn std::error::Error>> {
+    match evaluate(pkgbuild_path) {
+        Ok(result) => Ok(result),
+        Err(e) => {
+            println!(
+                "=> \x1b[31;1mERR:\x1b[0m {e}\n=> \x1b[33;1mTRY:\x1b[0m Falling back to parsing it.."
+            );
+
+            Ok(parse(&fs::read_to_string(pkgbuild_path)?))
         }
-        Err(err) => println!("=> \x1b[31;1mERR:\x1b[0m {err}"),
-    };
+    }
 }
 
-fn make(pkgbuild_path: &Path, src_dir: &Path) {
-    let content = fs::read_to_string(pkgbuild_path).unwrap();
+fn make(
+    pkgbuild_path: &Path,
+    src_dir: &Path,
+    options: &Options,
+) -> Result<Vec<Package>, Box<dyn std::error::Error>> {
     let src_dir_str: &str = src_dir.to_str().expect("failed");
+    let srcdir = src_dir.join("src");
 
-    let result: ParseResult = parse(&content);
+    let mut result: ParseResult = load(pkgbuild_path)?;
 
-    let url: &str = &result.url.expect("Failed");
-    let mut e_pkgname = result // conv to str slice and specify type
-        .variables
-        .get("pkgname")
-        .map(|s| s.as_str())
-        .unwrap_or("null")
-        .replace(")", "") // TODO: fix this
-        .replace("(", "");
+    let mut pkgnames: Vec<String> = result.array("pkgname");
 
-    //TODO: use if let some when u figure it out
-    if e_pkgname == "null" {
+    if pkgnames.is_empty() {
         println!("=> \x1b[1mINFO:\x1b[0m Using pkgbase instead..");
-        e_pkgname = result
-            .variables
-            .get("pkgbase")
-            .map(|s| s.as_str())
-            .unwrap_or("no")
-            .to_string();
+        pkgnames = result.array("pkgbase");
     } else {
         println!("=> \x1b[1mINFO:\x1b[0m Using pkgname..");
 
-        println!("{}", e_pkgname);
+        println!("{}", pkgnames.join(" "));
     }
 
-    let pkgnames: Vec<&str> = Vec::from_iter(e_pkgname.splitn(32usize, " ")); // TODO: resize, 0usize doesn't work.
+    let mut installed: Vec<Package> = vec![];
 
-    for i in pkgnames.iter() {
-        let formatted_pkgname = format_pkgbuild(i, &content, src_dir_str, i);
+    download_pkgbuild(&result, src_dir, &srcdir, options)?;
 
-        let pkg_fn_name;
-        let fmt_name = format!("package_{formatted_pkgname}");
+    // prepare() -> pkgver() -> build() -> check() -> package(), all of them optional.
+    // prepare() goes first since pkgver() may describe a checkout it patches or tags
+    let build_pkgdir = src_dir.join("pkg");
 
-        if let Some(_pkg) = result.functions.get("package") {
-            println!("=> \x1b[32;1mSUC:\x1b[0m Found package()!");
-            pkg_fn_name = "package";
+    if result.functions.contains_key("prepare") {
+        if options.noprepare {
+            println!("=> \x1b[1mINFO:\x1b[0m Skipping prepare()..");
+        } else {
+            println!("=> \x1b[32;1mSUC:\x1b[0m Running prepare() function!");
+            run_function(src_dir_str, "prepare", &srcdir, &build_pkgdir, None)?;
+        }
+    }
+
+    if result.functions.contains_key("pkgver") {
+        println!("=> \x1b[32;1mSUC:\x1b[0m Running pkgver() function!");
 
-            download_pkgbuild(pkg_fn_name, url, &content, src_dir_str, i);
+        if update_pkgver(pkgbuild_path, src_dir_str, &srcdir)? {
+            result = load(pkgbuild_path)?;
+        }
+    }
+
+    let version = full_version(&result);
+
+    if result.functions.contains_key("build") {
+        println!("=> \x1b[32;1mSUC:\x1b[0m Running build() function!");
+        run_function(src_dir_str, "build", &srcdir, &build_pkgdir, None)?;
+    }
+
+    if result.functions.contains_key("check") {
+        if options.nocheck || result.array("options").contains(&"!check".to_string()) {
+            println!("=> \x1b[1mINFO:\x1b[0m Skipping check()..");
+        } else {
+            println!("=> \x1b[32;1mSUC:\x1b[0m Running check() function!");
+            run_function(src_dir_str, "check", &srcdir, &build_pkgdir, None)?;
+        }
+    }
+
+    for formatted_pkgname in pkgnames {
+        let pkgdir = staging::prepare(&formatted_pkgname)?;
+
+        let pkg_fn_name = if result.functions.contains_key("package") {
+            println!("=> \x1b[32;1mSUC:\x1b[0m Found package()!");
+            "package".to_string()
         } else {
             println!(
                 "=> \x1b[33;1mTRY:\x1b[0m Trying package_{}()",
                 formatted_pkgname
             );
-            pkg_fn_name = &fmt_name;
-
-            download_pkgbuild(pkg_fn_name, url, &content, src_dir_str, i);
-        }
+            format!("package_{formatted_pkgname}")
+        };
+
+        println!("=> \x1b[32;1mSUC:\x1b[0m Running {pkg_fn_name}() function!");
+        // kept in the PKGBUILD dir, the sandbox lets fakeroot write there
+        let state = src_dir.join(format!("{formatted_pkgname}.fakeroot"));
+        let _ = fs::remove_file(&state);
+
+        run_function(src_dir_str, &pkg_fn_name, &srcdir, &pkgdir, Some(&state))?;
+        staging::record_owners(&pkgdir, state.exists().then_some(state.as_path()))?;
+
+        installed.push(Package {
+            name: formatted_pkgname,
+            version: version.clone(),
+            build_system: "pkgbuild".to_string(),
+            ..Default::default()
+        });
     }
-}
 
-fn format_archive(result: &ParseResult, pkgname: &str) -> String {
-    let archive: &str = result
-        .variables
-        .get("_archive")
-        .map(|s| s.as_str())
-        .unwrap_or("null");
-
-    let _pkgname: &str = result
-        .variables
-        .get("_pkgname")
-        .map(|s| s.as_str())
-        .unwrap_or("null");
-    let pkgver: &str = result
-        .variables
-        .get("pkgver")
-        .map(|s| s.as_str())
-        .unwrap_or("1.0.0");
-    let _name: &str = result
-        .variables
-        .get("_name")
-        .map(|s| s.as_str())
-        .unwrap_or(pkgname);
-    let pkgbase: &str = result
-        .variables
-        .get("pkgbase")
-        .map(|s| s.as_str())
-        .unwrap_or(pkgname);
-
-    let formatted: String = archive
-        .replace("$pkgver", pkgver)
-        .replace("$pkgname", pkgname)
-        .replace("$_pkgname", _pkgname)
-        .replace("$pkgbase", pkgbase)
-        .replace("(", "")
-        .replace(")", ""); //TODO: replace with sed;
-    formatted
+    Ok(installed)
 }
 
-fn format_pkgbuild(input: &str, content: &str, src_dir_str: &str, pkgname: &str) -> String {
-    let result: ParseResult = parse(&content);
-
-    let _pkgname: &str = result
-        .variables
-        .get("_pkgname")
-        .map(|s| s.as_str())
-        .unwrap_or("null");
-    let pkgbase: &str = result
-        .variables
-        .get("pkgbase")
-        .map(|s| s.as_str())
-        .unwrap_or("null");
-    let pkgver: &str = result
-        .variables
-        .get("pkgver")
-        .map(|s| s.as_str())
-        .unwrap_or("1.0.0");
-    let _name: &str = result
-        .variables
-        .get("_name")
-        .map(|s| s.as_str())
-        .unwrap_or(pkgname);
-    let pkg_url: &str = result
-        .variables
-        .get("url")
-        .map(|s| s.as_str())
-        .unwrap_or("https://popbugs.orgy");
-
-    let formatted = input
-        .replace("$srcdir/", src_dir_str)
-        .replace("$pkgver", pkgver)
-        .replace("$pkgname", pkgname)
-        .replace("$_pkgname", _pkgname)
-        .replace("$_archive", &format_archive(&result, pkgname))
-        .replace("$pkgbase", pkgbase)
-        .replace("$_name", _name)
-        .replace("$url", pkg_url)
-        .replace("${url}", pkg_url)
-        .replace("${folder}", "linux-unpacked")
-        .replace("${pkgver}", pkgver)
-        .replace("${pkgname}", pkgname)
-        .replace("{,.sig}", "")
-        .replace("git+", "")
-        .replace("(", ""); // TODO: could just use sed btw
-    // .replace(")", "");
-    formatted
+// epoch:pkgver-pkgrel, the way pacman prints it
+pub fn full_version(result: &ParseResult) -> String {
+    let pkgver = result.scalar("pkgver").unwrap_or("1.0.0");
+    let pkgrel = result.scalar("pkgrel").unwrap_or("1");
+
+    match result.scalar("epoch") {
+        Some(epoch) => format!("{epoch}:{pkgver}-{pkgrel}"),
+        None => format!("{pkgver}-{pkgrel}"),
+    }
 }
@@ -0,0 +1,206 @@
+// Verifies fetched sources against the *sums=() arrays, matched index by index to source=().
+
+use super::{parser::ParseResult, sources::Source};
+use blake2::Blake2b512;
+use md5::Md5;
+use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+const ALGORITHMS: [&str; 6] = ["sha256", "sha512", "b2", "md5", "sha224", "sha384"];
+
+pub fn verify(
+    result: &ParseResult,
+    sources: &[Source],
+    fetched: &[PathBuf],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut checked = false;
+    let mut failed: Vec<String> = vec![];
+
+    for algorithm in ALGORITHMS {
+        let sums = result.arch_array(&format!("{algorithm}sums"), std::env::consts::ARCH);
+
+        if sums.is_empty() {
+            continue;
+        }
+
+        if sums.len() != sources.len() {
+            return Err(format!(
+                "{algorithm}sums has {} entries but there are {} sources",
+                sums.len(),
+                sources.len()
+            )
+            .into());
+        }
+
+        checked = true;
+        println!("=> \x1b[33;1mTRY:\x1b[0m Validating sources with {algorithm}sums..");
+
+        for ((source, path), expected) in sources.iter().zip(fetched).zip(&sums) {
+            if expected == "SKIP" {
+                println!("    {} ... Skipped", source.name);
+                continue;
+            }
+
+            let actual = if path.is_dir() {
+                None
+            } else {
+                Some(digest(algorithm, path)?)
+            };
+
+            if actual.as_deref() == Some(expected.to_lowercase().as_str()) {
+                println!("    {} ... Passed", source.name);
+            } else {
+                println!("    {} ... \x1b[31;1mFAILED\x1b[0m", source.name);
+                println!("        expected: {expected}");
+                println!(
+                    "        got:      {}",
+                    actual.unwrap_or_else(|| "(directory, needs SKIP)".to_string())
+                );
+
+                failed.push(format!("{} ({algorithm})", source.name));
+            }
+        }
+    }
+
+    if !checked && !sources.is_empty() {
+        return Err("integrity checks are missing, use --skip-integrity to build anyway".into());
+    }
+
+    if !failed.is_empty() {
+        return Err(format!(
+            "one or more files did not pass the validity check: {}",
+            failed.join(", ")
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn digest(algorithm: &str, path: &Path) -> io::Result<String> {
+    match algorithm {
+        "sha256" => hash::<Sha256>(path),
+        "sha512" => hash::<Sha512>(path),
+        "sha224" => hash::<Sha224>(path),
+        "sha384" => hash::<Sha384>(path),
+        "b2" => hash::<Blake2b512>(path),
+        _ => hash::<Md5>(path),
+    }
+}
+
+fn hash<D: Digest + Write>(path: &Path) -> io::Result<String> {
+    let mut hasher = D::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compilers::pkgbuild::{parser, sources};
+    use std::fs;
+
+    const HELLO: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+    const WORLD: &str = "e258d248fda94c63753607f7c4494ee0fcbe92f1a76bfdac795c9d84101eb317";
+
+    // writes hello.txt and world.txt, parses the PKGBUILD and runs verify() over its sources
+    fn check(name: &str, pkgbuild: &str) -> Result<(), String> {
+        let dir = std::env::temp_dir().join(format!("uvi-checksums-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("hello.txt"), "hello\n").unwrap();
+        fs::write(dir.join("world.txt"), "world\n").unwrap();
+
+        let result = parser::parse(pkgbuild);
+        let sources = sources::sources(&result);
+        let fetched: Vec<PathBuf> = sources.iter().map(|source| source.location(&dir)).collect();
+
+        let checked = verify(&result, &sources, &fetched).map_err(|e| e.to_string());
+        fs::remove_dir_all(&dir).unwrap();
+
+        checked
+    }
+
+    #[test]
+    fn matching_sums() {
+        let pkgbuild = format!(
+            "source=(hello.txt world.txt)\nsha256sums=('{HELLO}' '{}')\nmd5sums=(b1946ac92492d2347c6235b4d2611184 SKIP)",
+            WORLD.to_uppercase()
+        );
+
+        assert_eq!(check("match", &pkgbuild), Ok(()));
+    }
+
+    #[test]
+    fn mismatch_names_the_file() {
+        let pkgbuild = format!("source=(hello.txt world.txt)\nsha256sums=('{HELLO}' '{HELLO}')");
+
+        assert_eq!(
+            check("mismatch", &pkgbuild),
+            Err(
+                "one or more files did not pass the validity check: world.txt (sha256)".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn skip_checks_nothing() {
+        assert_eq!(
+            check("skip", "source=(hello.txt world.txt)\nb2sums=(SKIP SKIP)"),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn sums_and_sources_must_line_up() {
+        let pkgbuild = format!("source=(hello.txt world.txt)\nsha256sums=('{HELLO}')");
+
+        assert_eq!(
+            check("length", &pkgbuild),
+            Err("sha256sums has 1 entries but there are 2 sources".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_sums() {
+        assert_eq!(
+            check("missing", "source=(hello.txt world.txt)"),
+            Err("integrity checks are missing, use --skip-integrity to build anyway".to_string())
+        );
+
+        // nothing to check, nothing missing
+        assert_eq!(check("nothing", "pkgname=foo"), Ok(()));
+    }
+
+    #[test]
+    fn arch_specific_sums_follow_arch_specific_sources() {
+        let arch = std::env::consts::ARCH;
+        let pkgbuild = format!(
+            "source=(hello.txt)\nsource_{arch}=(world.txt)\nsource_fakearch=(missing.txt)\n\
+             sha256sums=('{HELLO}')\nsha256sums_{arch}=('{WORLD}')\nsha256sums_fakearch=(SKIP)"
+        );
+
+        assert_eq!(check("arch", &pkgbuild), Ok(()));
+
+        // swapped, so world.txt is checked against hello's sum
+        let pkgbuild = format!(
+            "source=(hello.txt)\nsource_{arch}=(world.txt)\n\
+             sha256sums=('{HELLO}')\nsha256sums_{arch}=('{HELLO}')"
+        );
+
+        assert_eq!(
+            check("arch-mismatch", &pkgbuild),
+            Err(
+                "one or more files did not pass the validity check: world.txt (sha256)".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,133 @@
+// Sources the PKGBUILD in a restricted bash with an empty environment and reads back
+// every variable, array and function it defined. Bash does the expansion, so
+// ${pkgver//./_}, $(...) and friends all come out the way makepkg would see them.
+// Top-level code runs here on every load, so with --sandbox it's jailed like a build,
+// without network and with nothing writable.
+
+use super::parser::{ParseResult, Value, parse};
+use crate::sandbox;
+use std::{fs, path::Path};
+
+// prints `S\0name\0value\0` for scalars, `A\0name\0count\0items..` for arrays
+// and `F\0name\0` for functions, skipping whatever bash defined on its own
+const DUMP_SCRIPT: &str = r#"
+declare -A __uvi_builtin
+for __uvi_v in $(compgen -v); do __uvi_builtin[$__uvi_v]=1; done
+
+source PKGBUILD || exit 1
+
+for __uvi_v in $(compgen -v); do
+    [[ -n ${__uvi_builtin[$__uvi_v]} || $__uvi_v == __uvi_* ]] && continue
+
+    case "$(declare -p "$__uvi_v")" in
+        "declare -a"*)
+            declare -n __uvi_ref="$__uvi_v"
+            printf 'A\0%s\0%s\0' "$__uvi_v" "${#__uvi_ref[@]}"
+            printf '%s\0' "${__uvi_ref[@]}"
+            unset -n __uvi_ref
+            ;;
+        "declare -A"*) ;;
+        *) printf 'S\0%s\0%s\0' "$__uvi_v" "${!__uvi_v}" ;;
+    esac
+done
+
+for __uvi_f in $(compgen -A function); do
+    printf 'F\0%s\0' "$__uvi_f"
+done
+"#;
+
+pub fn evaluate(pkgbuild_path: &Path) -> Result<ParseResult, Box<dyn std::error::Error>> {
+    let dir = match pkgbuild_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::canonicalize(parent)?,
+        _ => fs::canonicalize(".")?,
+    };
+    let content = fs::read_to_string(pkgbuild_path)?;
+
+    // the static parse keeps function bodies exactly as written
+    let written = parse(&content);
+
+    let output = sandbox::command(
+        &dir.to_string_lossy(),
+        &[],
+        "env",
+        &[
+            "-i",
+            "PATH=/usr/bin:/bin",
+            &format!("CARCH={}", std::env::consts::ARCH),
+            "bash",
+            "--noprofile",
+            "--norc",
+            "-r",
+            "-c",
+            DUMP_SCRIPT,
+        ],
+    )
+    .output()?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "bash failed to source the PKGBUILD: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.split('\0');
+    let mut result = ParseResult::default();
+
+    while let Some(kind) = fields.next() {
+        match kind {
+            "S" => {
+                let name = fields.next().unwrap_or_default().to_string();
+                let value = fields.next().unwrap_or_default().to_string();
+
+                result.variables.insert(name, Value::Scalar(value));
+            }
+            "A" => {
+                let name = fields.next().unwrap_or_default().to_string();
+                let count: usize = fields.next().unwrap_or_default().parse()?;
+                let items: Vec<String> = fields.by_ref().take(count).map(String::from).collect();
+
+                result.variables.insert(name, Value::Array(items));
+            }
+            "F" => {
+                let name = fields.next().unwrap_or_default().to_string();
+                let body = written.functions.get(&name).cloned().unwrap_or_default();
+
+                result.functions.insert(name, body);
+            }
+            "" => continue,
+            other => return Err(format!("unexpected output from bash: {other}").into()),
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bash_expands_what_the_parser_reads() {
+        let dir = std::env::temp_dir().join(format!("uvi-evaluate-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let fixture =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pkgbuild/linux.PKGBUILD");
+        fs::copy(fixture, dir.join("PKGBUILD")).unwrap();
+
+        let result = evaluate(&dir.join("PKGBUILD")).unwrap();
+
+        assert_eq!(result.scalar("_srctag"), Some("v6.9.7-arch1"));
+        assert_eq!(result.array("source").len(), 5);
+
+        // the eval loop at the end defines the split package functions, only bash sees those
+        assert!(result.functions.contains_key("package_linux-headers"));
+        assert!(result.functions["prepare"].starts_with("cd $_srcname"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
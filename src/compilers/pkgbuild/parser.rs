@@ -0,0 +1,1217 @@
+// Parser for the subset of bash PKGBUILDs are written in.
+// Handles quoting, multi-line arrays, comments, brace expansion, ${var} expansion
+// (including the usual //, %, #, :- operators) and functions with nested braces.
+// Anything it doesn't understand at the top level (if blocks, commands) is skipped.
+
+use regex::Regex;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Scalar(String),
+    Array(Vec<String>),
+}
+
+#[derive(Debug, Default)]
+pub struct ParseResult {
+    pub variables: HashMap<String, Value>,
+    pub functions: HashMap<String, String>,
+}
+
+impl ParseResult {
+    // like $var in bash, arrays give their first element
+    pub fn scalar(&self, name: &str) -> Option<&str> {
+        match self.variables.get(name)? {
+            Value::Scalar(value) => Some(value.as_str()),
+            Value::Array(values) => values.first().map(|s| s.as_str()),
+        }
+    }
+
+    pub fn array(&self, name: &str) -> Vec<String> {
+        match self.variables.get(name) {
+            Some(Value::Scalar(value)) => vec![value.clone()],
+            Some(Value::Array(values)) => values.clone(),
+            None => vec![],
+        }
+    }
+
+    // source + source_x86_64 etc.
+    pub fn arch_array(&self, name: &str, arch: &str) -> Vec<String> {
+        let mut values = self.array(name);
+        values.extend(self.array(&format!("{name}_{arch}")));
+        values
+    }
+}
+
+pub fn parse(content: &str) -> ParseResult {
+    let mut parser = Parser {
+        chars: content.chars().collect(),
+        pos: 0,
+        result: ParseResult::default(),
+    };
+
+    parser.run();
+    parser.result
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    result: ParseResult,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn text(&self, start: usize, end: usize) -> String {
+        self.chars[start..end].iter().collect()
+    }
+
+    fn run(&mut self) {
+        while self.pos < self.chars.len() {
+            match self.peek() {
+                Some(c) if c.is_whitespace() || c == ';' => self.pos += 1,
+                Some('#') => self.skip_comment(),
+                Some('\\') if self.peek_at(1) == Some('\n') => self.pos += 2,
+                Some(_) => self.statement(),
+                None => break,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn skip_blanks(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ' ' || c == '\t' {
+                self.pos += 1;
+            } else if c == '\\' && self.peek_at(1) == Some('\n') {
+                self.pos += 2;
+            } else {
+                break;
+            }
+        }
+    }
+
+    // whitespace, newlines and comments
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else if c == '#' {
+                self.skip_comment();
+            } else if c == '\\' && self.peek_at(1) == Some('\n') {
+                self.pos += 2;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn identifier(&mut self) -> Option<String> {
+        let start = self.pos;
+
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => self.pos += 1,
+            _ => return None,
+        }
+
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+
+        Some(self.text(start, self.pos))
+    }
+
+    fn statement(&mut self) {
+        let start = self.pos;
+
+        let name = match self.identifier() {
+            Some(name) => name,
+            None => return self.skip_command(),
+        };
+
+        if name == "function" && matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.skip_blanks();
+
+            if let Some(name) = self.identifier() {
+                let name = self.function_name(name);
+                self.skip_blanks();
+
+                if self.peek() == Some('(') && self.peek_at(1) == Some(')') {
+                    self.pos += 2;
+                }
+
+                return self.function(name);
+            }
+
+            return self.skip_command();
+        }
+
+        match (self.peek(), self.peek_at(1)) {
+            (Some('='), _) => {
+                self.pos += 1;
+                self.assignment(name, false);
+            }
+            (Some('+'), Some('=')) => {
+                self.pos += 2;
+                self.assignment(name, true);
+            }
+            _ => {
+                let name = self.function_name(name);
+                self.skip_blanks();
+
+                if self.peek() == Some('(') && self.peek_at(1) == Some(')') {
+                    self.pos += 2;
+                    self.function(name);
+                } else {
+                    self.pos = start;
+                    self.skip_command();
+                }
+            }
+        }
+    }
+
+    // function names may also contain things like `-` (package_foo-headers)
+    fn function_name(&mut self, mut name: String) -> String {
+        while let Some(c) = self.peek().filter(|c| matches!(c, '-' | '.' | '+' | ':')) {
+            name.push(c);
+            self.pos += 1;
+            name.push_str(&self.identifier().unwrap_or_default());
+        }
+
+        name
+    }
+
+    fn assignment(&mut self, name: String, append: bool) {
+        let value = if self.peek() == Some('(') {
+            self.pos += 1;
+            Value::Array(self.array_items())
+        } else {
+            let raw = self.raw_word(false);
+            Value::Scalar(expand(&raw, &self.result.variables))
+        };
+
+        let value = match (append, self.result.variables.remove(&name), value) {
+            (true, Some(Value::Array(mut old)), Value::Array(new)) => {
+                old.extend(new);
+                Value::Array(old)
+            }
+            (true, Some(Value::Scalar(old)), Value::Array(new)) => {
+                let mut values = vec![old];
+                values.extend(new);
+                Value::Array(values)
+            }
+            (true, Some(Value::Array(mut old)), Value::Scalar(new)) => {
+                match old.first_mut() {
+                    Some(first) => first.push_str(&new),
+                    None => old.push(new),
+                }
+                Value::Array(old)
+            }
+            (true, Some(Value::Scalar(old)), Value::Scalar(new)) => Value::Scalar(old + &new),
+            (_, _, value) => value,
+        };
+
+        self.result.variables.insert(name, value);
+    }
+
+    fn array_items(&mut self) -> Vec<String> {
+        let mut items = vec![];
+
+        loop {
+            self.skip_space();
+
+            match self.peek() {
+                Some(')') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => {
+                    let start = self.pos;
+                    let raw = self.raw_word(true);
+
+                    if self.pos == start {
+                        // something we can't read as a word, don't loop forever on it
+                        self.pos += 1;
+                        continue;
+                    }
+
+                    items.extend(expand_words(&raw, &self.result.variables));
+                }
+                None => break,
+            }
+        }
+
+        items
+    }
+
+    fn function(&mut self, name: String) {
+        self.skip_space();
+
+        if self.peek() != Some('{') {
+            return self.skip_command();
+        }
+
+        self.pos += 1;
+        let start = self.pos;
+        let mut depth = 1;
+
+        while let Some(c) = self.peek() {
+            match c {
+                '{' => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                '#' if self.at_word_start() => self.skip_comment(),
+                '<' if self.peek_at(1) == Some('<') && self.peek_at(2) != Some('<') => {
+                    self.heredoc()
+                }
+                _ => self.skip_quoted_or_char(),
+            }
+        }
+
+        let body = self.text(start, self.pos.min(self.chars.len()));
+        self.pos += 1;
+
+        self.result.functions.insert(name, body.trim().to_string());
+    }
+
+    fn at_word_start(&self) -> bool {
+        self.pos == 0
+            || self
+                .chars
+                .get(self.pos - 1)
+                .is_some_and(|c| c.is_whitespace() || *c == ';' || *c == '(')
+    }
+
+    // skips over `<<EOF ... EOF`, the body may contain anything
+    fn heredoc(&mut self) {
+        self.pos += 2;
+
+        let strip_tabs = self.peek() == Some('-');
+        if strip_tabs {
+            self.pos += 1;
+        }
+
+        self.skip_blanks();
+
+        let raw = self.raw_word(false);
+        let delimiter = raw.replace(['\'', '"', '\\'], "");
+
+        if delimiter.is_empty() {
+            return;
+        }
+
+        // rest of the line is still normal shell
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                break;
+            }
+            self.skip_quoted_or_char();
+        }
+
+        while self.pos < self.chars.len() {
+            self.pos += 1;
+
+            let line_start = self.pos;
+            while self.peek().is_some_and(|c| c != '\n') {
+                self.pos += 1;
+            }
+
+            let line = self.text(line_start, self.pos);
+            let line = if strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                line.as_str()
+            };
+
+            if line == delimiter {
+                break;
+            }
+        }
+    }
+
+    // steps over one char, or a whole quoted string / expansion if one starts here
+    fn skip_quoted_or_char(&mut self) {
+        match self.peek() {
+            Some('\\') => self.pos += 2,
+            Some('\'') => {
+                self.pos += 1;
+                while self.peek().is_some_and(|c| c != '\'') {
+                    self.pos += 1;
+                }
+                self.pos += 1;
+            }
+            Some('"') => {
+                self.pos += 1;
+                while let Some(c) = self.peek() {
+                    match c {
+                        '"' => break,
+                        '\\' => self.pos += 2,
+                        '$' => self.skip_dollar(),
+                        '`' => self.skip_backtick(),
+                        _ => self.pos += 1,
+                    }
+                }
+                self.pos += 1;
+            }
+            Some('$') => self.skip_dollar(),
+            Some('`') => self.skip_backtick(),
+            Some(_) => self.pos += 1,
+            None => {}
+        }
+    }
+
+    fn skip_backtick(&mut self) {
+        self.pos += 1;
+        while let Some(c) = self.peek() {
+            match c {
+                '`' => break,
+                '\\' => self.pos += 2,
+                _ => self.pos += 1,
+            }
+        }
+        self.pos += 1;
+    }
+
+    // $name, ${...} or $(...), nested quotes included
+    fn skip_dollar(&mut self) {
+        self.pos += 1;
+
+        let (open, close) = match self.peek() {
+            Some('{') => ('{', '}'),
+            Some('(') => ('(', ')'),
+            _ => return,
+        };
+
+        self.pos += 1;
+        let mut depth = 1;
+
+        while let Some(c) = self.peek() {
+            if c == open {
+                depth += 1;
+                self.pos += 1;
+            } else if c == close {
+                depth -= 1;
+                self.pos += 1;
+                if depth == 0 {
+                    break;
+                }
+            } else if c == '#' && open == '(' && self.at_word_start() {
+                self.skip_comment();
+            } else {
+                self.skip_quoted_or_char();
+            }
+        }
+    }
+
+    // one shell word with its quotes left in, ends at unquoted whitespace or an operator
+    fn raw_word(&mut self, in_array: bool) -> String {
+        let start = self.pos;
+
+        while let Some(c) = self.peek() {
+            match c {
+                c if c.is_whitespace() => break,
+                ';' | '&' | '|' | '<' | '>' => break,
+                ')' if in_array => break,
+                '(' => break,
+                _ => self.skip_quoted_or_char(),
+            }
+        }
+
+        self.pos = self.pos.min(self.chars.len());
+
+        self.text(start, self.pos).replace("\\\n", "")
+    }
+
+    // anything that isn't an assignment or function, up to the end of the line
+    fn skip_command(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                '\n' | ';' => break,
+                '#' if self.at_word_start() => self.skip_comment(),
+                '<' if self.peek_at(1) == Some('<') && self.peek_at(2) != Some('<') => {
+                    self.heredoc()
+                }
+                _ => self.skip_quoted_or_char(),
+            }
+        }
+    }
+}
+
+// expands a word that sits in an array, braces and "${arr[@]}" can turn it into many
+pub fn expand_words(raw: &str, vars: &HashMap<String, Value>) -> Vec<String> {
+    let mut words = vec![];
+
+    for word in brace_expand(raw) {
+        let bare = word.trim_matches('"');
+
+        if let Some(name) = bare
+            .strip_prefix("${")
+            .and_then(|rest| rest.strip_suffix("[@]}"))
+            .filter(|name| is_identifier(name))
+        {
+            match vars.get(name) {
+                Some(Value::Array(values)) => words.extend(values.iter().cloned()),
+                Some(Value::Scalar(value)) => words.push(value.clone()),
+                None => {}
+            }
+            continue;
+        }
+
+        words.push(expand(&word, vars));
+    }
+
+    words
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// foo{,.sig} -> foo foo.sig, only outside of quotes and not for ${..}
+fn brace_expand(raw: &str) -> Vec<String> {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut i = 0;
+    let mut quote: Option<char> = None;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        match (quote, c) {
+            (None, '\\') => i += 1,
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (Some('"'), '\\') => i += 1,
+            (None, '$') if chars.get(i + 1) == Some(&'{') => {
+                // step over the whole ${...}
+                let mut depth = 0;
+                while i < chars.len() {
+                    match chars[i] {
+                        '{' => depth += 1,
+                        '}' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    i += 1;
+                }
+            }
+            (None, '{') => {
+                if let Some((end, alternatives)) = brace_alternatives(&chars, i) {
+                    let prefix: String = chars[..i].iter().collect();
+                    let suffix: String = chars[end + 1..].iter().collect();
+
+                    return alternatives
+                        .iter()
+                        .flat_map(|alt| brace_expand(&format!("{prefix}{alt}{suffix}")))
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+
+        i += 1;
+    }
+
+    vec![raw.to_string()]
+}
+
+// comma separated parts of the brace group starting at `open`, None if it isn't one
+fn brace_alternatives(chars: &[char], open: usize) -> Option<(usize, Vec<String>)> {
+    let mut depth = 0;
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut i = open;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        match c {
+            '\\' => {
+                current.push(c);
+                if let Some(next) = chars.get(i + 1) {
+                    current.push(*next);
+                }
+                i += 1;
+            }
+            '{' => {
+                depth += 1;
+                if depth > 1 {
+                    current.push(c);
+                }
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    parts.push(current);
+
+                    return if parts.len() > 1 {
+                        Some((i, parts))
+                    } else {
+                        None
+                    };
+                }
+                current.push(c);
+            }
+            ',' if depth == 1 => parts.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+
+        i += 1;
+    }
+
+    None
+}
+
+// quote removal and parameter expansion for a single word
+pub fn expand(raw: &str, vars: &HashMap<String, Value>) -> String {
+    let chars: Vec<char> = raw.chars().collect();
+    let mut out = String::new();
+    let mut in_double = false;
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+
+        match c {
+            '\\' if !in_double => {
+                if let Some(next) = chars.get(i + 1).filter(|next| **next != '\n') {
+                    out.push(*next);
+                }
+                i += 2;
+            }
+            '\\' => {
+                match chars.get(i + 1) {
+                    Some(next) if matches!(next, '$' | '`' | '"' | '\\') => out.push(*next),
+                    Some('\n') => {}
+                    Some(next) => {
+                        out.push('\\');
+                        out.push(*next);
+                    }
+                    None => out.push('\\'),
+                }
+                i += 2;
+            }
+            '\'' if !in_double => {
+                i += 1;
+                while i < chars.len() && chars[i] != '\'' {
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                i += 1;
+            }
+            '"' => {
+                in_double = !in_double;
+                i += 1;
+            }
+            '$' => {
+                let (value, next) = dollar(&chars, i, vars);
+                out.push_str(&value);
+                i = next;
+            }
+            _ => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+// expands the $ at `start`, returns the value and the index after it
+fn dollar(chars: &[char], start: usize, vars: &HashMap<String, Value>) -> (String, usize) {
+    let mut i = start + 1;
+
+    match chars.get(i) {
+        Some('{') => {
+            let mut depth = 1;
+            i += 1;
+            let inner_start = i;
+
+            while i < chars.len() {
+                match chars[i] {
+                    '{' => depth += 1,
+                    '}' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            break;
+                        }
+                    }
+                    '\\' => i += 1,
+                    _ => {}
+                }
+                i += 1;
+            }
+
+            let inner: String = chars[inner_start..i.min(chars.len())].iter().collect();
+            (parameter(&inner, vars), i + 1)
+        }
+        Some('(') => {
+            // command substitution can't be evaluated here, keep it as written
+            let mut depth = 1;
+            i += 1;
+
+            while i < chars.len() && depth > 0 {
+                match chars[i] {
+                    '(' => depth += 1,
+                    ')' => depth -= 1,
+                    _ => {}
+                }
+                i += 1;
+            }
+
+            (chars[start..i].iter().collect(), i)
+        }
+        Some(c) if c.is_ascii_alphabetic() || *c == '_' => {
+            let name_start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+
+            let name: String = chars[name_start..i].iter().collect();
+            (lookup(&name, vars), i)
+        }
+        Some(c) if c.is_ascii_digit() || matches!(c, '@' | '*' | '#' | '?' | '!' | '-') => {
+            (String::new(), i + 1)
+        }
+        _ => ("$".to_string(), i),
+    }
+}
+
+fn lookup(name: &str, vars: &HashMap<String, Value>) -> String {
+    match vars.get(name) {
+        Some(Value::Scalar(value)) => value.clone(),
+        Some(Value::Array(values)) => values.first().cloned().unwrap_or_default(),
+        None => String::new(),
+    }
+}
+
+// the inside of ${...}
+fn parameter(inner: &str, vars: &HashMap<String, Value>) -> String {
+    if let Some(name) = inner.strip_prefix('#') {
+        return match name.strip_suffix("[@]").or(name.strip_suffix("[*]")) {
+            Some(array) => match vars.get(array) {
+                Some(Value::Array(values)) => values.len().to_string(),
+                Some(Value::Scalar(_)) => "1".to_string(),
+                None => "0".to_string(),
+            },
+            None => lookup(name, vars).chars().count().to_string(),
+        };
+    }
+
+    let name_end = inner
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(inner.len());
+    let name = &inner[..name_end];
+    let mut rest = &inner[name_end..];
+
+    let value = if let Some(after) = rest.strip_prefix('[') {
+        let close = after.find(']').unwrap_or(after.len());
+        let index = &after[..close];
+        rest = after.get(close + 1..).unwrap_or("");
+
+        match (vars.get(name), index) {
+            (Some(Value::Array(values)), "@" | "*") => values.join(" "),
+            (Some(Value::Array(values)), index) => index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| values.get(i).cloned())
+                .unwrap_or_default(),
+            (Some(Value::Scalar(value)), "@" | "*" | "0") => value.clone(),
+            _ => String::new(),
+        }
+    } else {
+        lookup(name, vars)
+    };
+
+    let is_set = vars.contains_key(name);
+
+    if rest.is_empty() {
+        return value;
+    }
+
+    if let Some(word) = rest.strip_prefix(":-") {
+        return if value.is_empty() {
+            expand(word, vars)
+        } else {
+            value
+        };
+    }
+    if let Some(word) = rest.strip_prefix(":+") {
+        return if value.is_empty() {
+            value
+        } else {
+            expand(word, vars)
+        };
+    }
+    if let Some(word) = rest.strip_prefix(":=") {
+        return if value.is_empty() {
+            expand(word, vars)
+        } else {
+            value
+        };
+    }
+    if let Some(word) = rest.strip_prefix('-') {
+        return if is_set { value } else { expand(word, vars) };
+    }
+    if let Some(word) = rest.strip_prefix('+') {
+        return if is_set {
+            expand(word, vars)
+        } else {
+            String::new()
+        };
+    }
+    if let Some(pattern) = rest.strip_prefix("##") {
+        return strip_prefix(&value, &expand(pattern, vars), true);
+    }
+    if let Some(pattern) = rest.strip_prefix('#') {
+        return strip_prefix(&value, &expand(pattern, vars), false);
+    }
+    if let Some(pattern) = rest.strip_prefix("%%") {
+        return strip_suffix(&value, &expand(pattern, vars), true);
+    }
+    if let Some(pattern) = rest.strip_prefix('%') {
+        return strip_suffix(&value, &expand(pattern, vars), false);
+    }
+    if let Some(spec) = rest.strip_prefix('/') {
+        return substitute(&value, spec, vars);
+    }
+    if rest == "^^" {
+        return value.to_uppercase();
+    }
+    if rest == ",," {
+        return value.to_lowercase();
+    }
+    if rest == "^" || rest == "," {
+        let mut chars = value.chars();
+        return match chars.next() {
+            Some(first) if rest == "^" => first.to_uppercase().chain(chars).collect(),
+            Some(first) => first.to_lowercase().chain(chars).collect(),
+            None => value,
+        };
+    }
+    if let Some(range) = rest.strip_prefix(':') {
+        let (offset, length) = match range.split_once(':') {
+            Some((offset, length)) => (offset, Some(length)),
+            None => (range, None),
+        };
+
+        let chars: Vec<char> = value.chars().collect();
+        let offset: i64 = offset.trim().parse().unwrap_or(0);
+        let start = if offset < 0 {
+            chars.len().saturating_sub(offset.unsigned_abs() as usize)
+        } else {
+            (offset as usize).min(chars.len())
+        };
+        let end = match length.and_then(|l| l.trim().parse::<i64>().ok()) {
+            Some(l) if l < 0 => chars.len().saturating_sub(l.unsigned_abs() as usize),
+            Some(l) => (start + l as usize).min(chars.len()),
+            None => chars.len(),
+        };
+
+        return chars[start..end.max(start)].iter().collect();
+    }
+
+    value
+}
+
+// ${var/pat/rep}, ${var//pat/rep}, ${var/#pat/rep} and ${var/%pat/rep}
+fn substitute(value: &str, spec: &str, vars: &HashMap<String, Value>) -> String {
+    let (mode, spec) = match spec.chars().next() {
+        Some('/') => ('/', &spec[1..]),
+        Some('#') => ('#', &spec[1..]),
+        Some('%') => ('%', &spec[1..]),
+        _ => (' ', spec),
+    };
+
+    let (pattern, replacement) = match split_unescaped(spec, '/') {
+        Some((pattern, replacement)) => (expand(pattern, vars), expand(replacement, vars)),
+        None => (expand(spec, vars), String::new()),
+    };
+
+    let re = match glob_regex(&pattern) {
+        Some(re) => re,
+        None => return value.to_string(),
+    };
+
+    let chars: Vec<char> = value.chars().collect();
+    let slice = |a: usize, b: usize| -> String { chars[a..b].iter().collect() };
+
+    match mode {
+        '#' => {
+            for end in (0..=chars.len()).rev() {
+                if re.is_match(&slice(0, end)) {
+                    return format!("{replacement}{}", slice(end, chars.len()));
+                }
+            }
+            value.to_string()
+        }
+        '%' => {
+            for start in 0..=chars.len() {
+                if re.is_match(&slice(start, chars.len())) {
+                    return format!("{}{replacement}", slice(0, start));
+                }
+            }
+            value.to_string()
+        }
+        _ => {
+            let mut out = String::new();
+            let mut i = 0;
+            let mut replaced = false;
+
+            while i < chars.len() {
+                let found = if replaced && mode != '/' {
+                    None
+                } else {
+                    ((i + 1)..=chars.len())
+                        .rev()
+                        .find(|end| re.is_match(&slice(i, *end)))
+                };
+
+                match found {
+                    Some(end) => {
+                        out.push_str(&replacement);
+                        i = end;
+                        replaced = true;
+                    }
+                    None => {
+                        out.push(chars[i]);
+                        i += 1;
+                    }
+                }
+            }
+
+            out
+        }
+    }
+}
+
+fn split_unescaped(spec: &str, sep: char) -> Option<(&str, &str)> {
+    let mut escaped = false;
+
+    for (i, c) in spec.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == sep {
+            return Some((&spec[..i], &spec[i + 1..]));
+        }
+    }
+
+    None
+}
+
+fn strip_prefix(value: &str, pattern: &str, longest: bool) -> String {
+    let re = match glob_regex(pattern) {
+        Some(re) => re,
+        None => return value.to_string(),
+    };
+
+    let ends: Vec<usize> = value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(value.len()))
+        .collect();
+
+    let found = if longest {
+        ends.iter().rev().find(|end| re.is_match(&value[..**end]))
+    } else {
+        ends.iter().find(|end| re.is_match(&value[..**end]))
+    };
+
+    match found {
+        Some(end) => value[*end..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn strip_suffix(value: &str, pattern: &str, longest: bool) -> String {
+    let re = match glob_regex(pattern) {
+        Some(re) => re,
+        None => return value.to_string(),
+    };
+
+    let starts: Vec<usize> = value
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(value.len()))
+        .collect();
+
+    let found = if longest {
+        starts.iter().find(|start| re.is_match(&value[**start..]))
+    } else {
+        starts
+            .iter()
+            .rev()
+            .find(|start| re.is_match(&value[**start..]))
+    };
+
+    match found {
+        Some(start) => value[..*start].to_string(),
+        None => value.to_string(),
+    }
+}
+
+// bash pattern -> anchored regex
+fn glob_regex(pattern: &str) -> Option<Regex> {
+    let mut re = String::from("^");
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => re.push_str("(?s:.*)"),
+            '?' => re.push('.'),
+            '\\' => {
+                if let Some(next) = chars.next() {
+                    re.push_str(&regex::escape(&next.to_string()));
+                }
+            }
+            '[' => {
+                re.push('[');
+                if chars.peek() == Some(&'!') || chars.peek() == Some(&'^') {
+                    chars.next();
+                    re.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        re.push('\\');
+                    }
+                    re.push(c);
+                }
+                re.push(']');
+            }
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    re.push('$');
+
+    Regex::new(&re).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, path::Path};
+
+    fn fixture(name: &str) -> ParseResult {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/pkgbuild")
+            .join(name);
+
+        parse(&fs::read_to_string(path).unwrap())
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn multiline_arrays_with_comments() {
+        let result = fixture("linux.PKGBUILD");
+
+        assert_eq!(
+            result.array("makedepends"),
+            strings(&[
+                "bc",
+                "cpio",
+                "gettext",
+                "libelf",
+                "pahole",
+                "perl",
+                "python",
+                "tar",
+                "xz",
+                "graphviz",
+                "imagemagick",
+                "python-sphinx",
+                "texlive-latexextra",
+            ])
+        );
+        assert_eq!(
+            result.array("validpgpkeys"),
+            strings(&[
+                "ABAF11C65A2970B130ABE3C479BE3E4300411886",
+                "647F28654894E3BD457199BE38DBBDC86092693E",
+            ])
+        );
+        assert_eq!(result.array("sha256sums").len(), 5);
+    }
+
+    #[test]
+    fn brace_expansion_and_pattern_operators() {
+        let result = fixture("linux.PKGBUILD");
+
+        assert_eq!(result.scalar("_srcname"), Some("linux-6.9.7"));
+        assert_eq!(result.scalar("_srctag"), Some("v6.9.7-arch1"));
+        assert_eq!(
+            result.array("source"),
+            strings(&[
+                "https://cdn.kernel.org/pub/linux/kernel/v6.x/linux-6.9.7.tar.xz",
+                "https://cdn.kernel.org/pub/linux/kernel/v6.x/linux-6.9.7.tar.sign",
+                "https://github.com/archlinux/linux/releases/download/v6.9.7-arch1/linux-v6.9.7-arch1.patch.zst",
+                "https://github.com/archlinux/linux/releases/download/v6.9.7-arch1/linux-v6.9.7-arch1.patch.zst.sig",
+                "config",
+            ])
+        );
+
+        // an array assigned after the functions, from ${pkgbase}
+        assert_eq!(
+            result.array("pkgname"),
+            strings(&["linux", "linux-headers", "linux-docs"])
+        );
+    }
+
+    #[test]
+    fn quoted_brace_expansion() {
+        let result = fixture("python-split.PKGBUILD");
+
+        assert_eq!(
+            result.array("source"),
+            strings(&[
+                "https://files.pythonhosted.org/packages/source/f/foo/foo-2.3.1.tar.gz",
+                "https://files.pythonhosted.org/packages/source/f/foo/foo-2.3.1.tar.gz.asc",
+            ])
+        );
+        assert_eq!(
+            result.array("depends"),
+            strings(&["python", "python-typing_extensions"])
+        );
+    }
+
+    #[test]
+    fn substitutions() {
+        let result = fixture("openssl-1.1.PKGBUILD");
+
+        assert_eq!(result.scalar("pkgver"), Some("1.1.1.w"));
+        assert_eq!(result.scalar("_tag"), Some("OpenSSL_1_1_1w"));
+        assert_eq!(
+            result.array("source"),
+            strings(&[
+                "https://github.com/openssl/openssl/archive/OpenSSL_1_1_1w.tar.gz",
+                "ca-dir.patch",
+            ])
+        );
+        assert!(result.functions["build"].contains("make depend"));
+    }
+
+    #[test]
+    fn arch_specific_sources() {
+        let result = fixture("visual-studio-code-bin.PKGBUILD");
+
+        assert_eq!(
+            result.arch_array("source", "x86_64"),
+            strings(&[
+                "visual-studio-code.desktop",
+                "visual-studio-code-url-handler.desktop",
+                "visual-studio-code.sh",
+                "code_x64_1.90.2.tar.gz::https://update.code.visualstudio.com/1.90.2/linux-x64/stable",
+            ])
+        );
+        assert_eq!(
+            result.arch_array("sha256sums", "aarch64").last().unwrap(),
+            "1d4b1e8f3e4c2b5d6e7f8a9b0c1d2e3f4a5b6c7d8e9f0a1b2c3d4e5f6a7b8c9d"
+        );
+
+        // a comment line in the middle of a quoted array
+        assert_eq!(result.array("optdepends").len(), 4);
+        assert_eq!(result.array("source_i686"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn functions_with_heredocs_and_nested_braces() {
+        let result = fixture("neovim-git.PKGBUILD");
+
+        let package = &result.functions["package"];
+
+        assert!(package.starts_with("cd \"${srcdir}\""));
+        assert!(package.contains("\tendfunction\n"));
+        assert!(package.ends_with("\"${pkgdir}\"/usr/share/nvim/archlinux.vim"));
+
+        assert_eq!(
+            result.functions["pkgver"],
+            "cd \"${pkgname}\"\n  git describe --long | sed 's/^v//;s/\\([^-]*-g\\)/r\\1/;s/-/./g'"
+        );
+        assert!(result.functions["_helper"].ends_with("done"));
+
+        let mut names: Vec<&str> = result.functions.keys().map(|name| name.as_str()).collect();
+        names.sort();
+
+        assert_eq!(names, ["_helper", "build", "check", "package", "pkgver"]);
+        assert_eq!(
+            result.array("provides"),
+            strings(&["neovim=0.10.0.r2972.g1c2a3b4", "vim-plugin-runtime"])
+        );
+    }
+
+    #[test]
+    fn split_package_functions() {
+        let result = fixture("python-split.PKGBUILD");
+
+        assert_eq!(
+            result.array("pkgname"),
+            strings(&["python-foo", "python-foo-bar", "python-foo-docs"])
+        );
+        assert!(result.functions["package_python-foo-bar"].starts_with("pkgdesc=\"Bar plugin"));
+        assert!(result.functions["package_python-foo-docs"].starts_with("pkgdesc+="));
+        assert!(result.functions.contains_key("package_python-foo"));
+
+        let linux = fixture("linux.PKGBUILD");
+
+        assert!(linux.functions.contains_key("_package-headers"));
+        assert!(linux.functions.contains_key("_package-docs"));
+        assert_eq!(
+            linux.functions["_package-headers"].lines().next(),
+            Some("pkgdesc=\"Headers and scripts for building modules for the $pkgdesc kernel\"")
+        );
+    }
+
+    #[test]
+    fn scalars_and_appends() {
+        let vars = parse("a=1\nb=(x y)\na+=2\nb+=(z)\nc=\"${b[1]}\"\nd=${#b[@]}\n").variables;
+
+        assert_eq!(vars["a"], Value::Scalar("12".to_string()));
+        assert_eq!(vars["b"], Value::Array(strings(&["x", "y", "z"])));
+        assert_eq!(vars["c"], Value::Scalar("y".to_string()));
+        assert_eq!(vars["d"], Value::Scalar("3".to_string()));
+    }
+}
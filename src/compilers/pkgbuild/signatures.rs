@@ -0,0 +1,484 @@
+// Checks detached .sig/.asc/.sign sources with gpg, the signing key has to be in validpgpkeys=().
+// Git sources ending in `?signed` get their tag or commit checked the same way.
+
+use super::{
+    parser::ParseResult,
+    sources::{Source, SourceKind},
+};
+use crate::git::{self, GitUrl};
+use std::{
+    fs::File,
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const SIGNATURE_EXTENSIONS: [&str; 3] = [".sig", ".asc", ".sign"];
+
+// signatures made over the uncompressed tarball, like kernel.org's .tar.sign
+const DECOMPRESSORS: [(&str, &str); 4] = [
+    (".gz", "gzip"),
+    (".xz", "xz"),
+    (".zst", "zstd"),
+    (".bz2", "bzip2"),
+];
+
+pub fn verify(
+    result: &ParseResult,
+    sources: &[Source],
+    fetched: &[PathBuf],
+    gpg_home: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let valid_keys: Vec<String> = result
+        .array("validpgpkeys")
+        .iter()
+        .map(|key| key.replace(' ', "").to_uppercase())
+        .collect();
+
+    let mut failed: Vec<String> = vec![];
+    let mut announced = false;
+
+    for (source, signature) in sources.iter().zip(fetched) {
+        if source.kind == SourceKind::Git {
+            let git_url = GitUrl::parse(&source.url);
+
+            if !git_url.signed {
+                continue;
+            }
+
+            if !announced {
+                println!("=> \x1b[33;1mTRY:\x1b[0m Verifying source file signatures with gpg..");
+                announced = true;
+            }
+
+            let status = git::verify(signature, &git_url.reference, gpg_home)?;
+            let label = format!("{} ({})", source.name, git::describe(&git_url.reference));
+
+            report(
+                &label,
+                check_status(&status, &valid_keys),
+                &valid_keys,
+                &mut failed,
+            );
+            continue;
+        }
+
+        let signed_name = match SIGNATURE_EXTENSIONS
+            .iter()
+            .find_map(|ext| source.name.strip_suffix(ext))
+        {
+            Some(name) => name,
+            None => continue,
+        };
+
+        if !announced {
+            println!("=> \x1b[33;1mTRY:\x1b[0m Verifying source file signatures with gpg..");
+            announced = true;
+        }
+
+        let signed = sources
+            .iter()
+            .zip(fetched)
+            .find(|(other, _)| other.name == signed_name)
+            .map(|(_, path)| (path.clone(), None))
+            .or_else(|| {
+                DECOMPRESSORS.iter().find_map(|(ext, tool)| {
+                    let name = format!("{signed_name}{ext}");
+
+                    sources
+                        .iter()
+                        .zip(fetched)
+                        .find(|(other, _)| other.name == name)
+                        .map(|(_, path)| (path.clone(), Some(*tool)))
+                })
+            });
+
+        let problem = match signed {
+            Some((path, decompressor)) if signature.exists() => check_status(
+                &gpg_verify(signature, &path, decompressor, gpg_home)?,
+                &valid_keys,
+            ),
+            Some(_) => Some("signature file is missing".to_string()),
+            None => Some(format!(
+                "can't find {signed_name} to check the signature against"
+            )),
+        };
+
+        report(&source.name, problem, &valid_keys, &mut failed);
+    }
+
+    if !failed.is_empty() {
+        return Err(format!(
+            "one or more PGP signatures could not be verified: {}",
+            failed.join(", ")
+        )
+        .into());
+    }
+
+    Ok(())
+}
+
+fn report(name: &str, problem: Option<String>, valid_keys: &[String], failed: &mut Vec<String>) {
+    match problem {
+        None => {
+            if valid_keys.is_empty() {
+                println!("    {name} ... Passed (no validpgpkeys, trusting your keyring)");
+            } else {
+                println!("    {name} ... Passed");
+            }
+        }
+        Some(problem) => {
+            println!("    {name} ... \x1b[31;1mFAILED\x1b[0m ({problem})");
+            failed.push(name.to_string());
+        }
+    }
+}
+
+// what's wrong with a signature according to gpg's status lines, None if it's good
+pub fn check_status(status: &str, valid_keys: &[String]) -> Option<String> {
+    let fingerprint = status.lines().find_map(|line| {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+
+        // the last field of VALIDSIG is the primary key, the first the (sub)key that signed
+        match fields.as_slice() {
+            ["[GNUPG:]", "VALIDSIG", .., primary] if fields.len() >= 12 => {
+                Some(primary.to_string())
+            }
+            ["[GNUPG:]", "VALIDSIG", fingerprint, ..] => Some(fingerprint.to_string()),
+            _ => None,
+        }
+    });
+
+    if status.contains("NO_PUBKEY") {
+        return Some("unknown public key, import it with `gpg --recv-keys`".to_string());
+    }
+    if status.contains("BADSIG") {
+        return Some("bad signature".to_string());
+    }
+    if status.contains("EXPKEYSIG") || status.contains("REVKEYSIG") {
+        return Some("signed with an expired or revoked key".to_string());
+    }
+
+    match fingerprint {
+        None => Some("no valid signature".to_string()),
+        Some(fpr) if !valid_keys.is_empty() && !valid_keys.contains(&fpr) => {
+            Some(format!("key {fpr} is not in validpgpkeys"))
+        }
+        Some(_) => None,
+    }
+}
+
+// runs gpg --verify and hands back its --status-fd output
+fn gpg_verify(
+    signature: &Path,
+    signed: &Path,
+    decompressor: Option<&str>,
+    gpg_home: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut gpg = Command::new("gpg");
+
+    if let Some(home) = gpg_home {
+        gpg.arg("--homedir").arg(home);
+    }
+
+    gpg.args(["--batch", "--status-fd", "1", "--verify"])
+        .arg(signature)
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+
+    let mut child = gpg.spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+
+    let copied = match decompressor {
+        Some(tool) => {
+            let mut decompress = Command::new(tool)
+                .arg("-dc")
+                .arg(signed)
+                .stdout(Stdio::piped())
+                .spawn()?;
+
+            let copied = io::copy(decompress.stdout.as_mut().unwrap(), &mut stdin);
+
+            // if gpg gave up early nobody drains the pipe anymore, closing it lets the
+            // decompressor die of SIGPIPE instead of blocking forever
+            drop(decompress.stdout.take());
+            decompress.wait()?;
+            copied
+        }
+        None => io::copy(&mut File::open(signed)?, &mut stdin),
+    };
+
+    drop(stdin);
+
+    // gpg stops reading early when the signature itself is broken, its status says why
+    if let Err(e) = copied
+        && e.kind() != io::ErrorKind::BrokenPipe
+    {
+        return Err(e.into());
+    }
+
+    let output = child.wait_with_output()?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compilers::pkgbuild::parser;
+    use flate2::{Compression, write::GzEncoder};
+    use std::{fs, io::Write, path::PathBuf};
+
+    // recorded from gpg 2.4, the signing key is a subkey of D2D00AA0..
+    const VALIDSIG_SUBKEY: &str =
+        include_str!("../../../tests/fixtures/gpg/validsig-subkey.status");
+    const BADSIG: &str = include_str!("../../../tests/fixtures/gpg/badsig.status");
+    const NO_PUBKEY: &str = include_str!("../../../tests/fixtures/gpg/no-pubkey.status");
+    const EXPKEYSIG: &str = include_str!("../../../tests/fixtures/gpg/expkeysig.status");
+
+    const PRIMARY: &str = "D2D00AA0B3603E94220DC525C10BE776126F362D";
+    const SUBKEY: &str = "0E44996E9582F0B2393AB85F27E53FB7187FEDBB";
+
+    #[test]
+    fn validsig_matches_the_primary_key() {
+        assert_eq!(check_status(VALIDSIG_SUBKEY, &[PRIMARY.to_string()]), None);
+
+        // no validpgpkeys, whatever the keyring trusts goes
+        assert_eq!(check_status(VALIDSIG_SUBKEY, &[]), None);
+
+        // validpgpkeys lists primary keys, like makepkg a subkey there doesn't count
+        assert_eq!(
+            check_status(VALIDSIG_SUBKEY, &[SUBKEY.to_string()]),
+            Some(format!("key {PRIMARY} is not in validpgpkeys"))
+        );
+    }
+
+    #[test]
+    fn key_not_in_validpgpkeys() {
+        let other = "ABAF11C65A2970B130ABE3C479BE3E4300411886".to_string();
+
+        assert_eq!(
+            check_status(VALIDSIG_SUBKEY, &[other]),
+            Some(format!("key {PRIMARY} is not in validpgpkeys"))
+        );
+    }
+
+    #[test]
+    fn bad_unknown_and_expired_signatures() {
+        let valid = [PRIMARY.to_string()];
+
+        assert_eq!(
+            check_status(BADSIG, &valid),
+            Some("bad signature".to_string())
+        );
+        assert_eq!(
+            check_status(NO_PUBKEY, &valid),
+            Some("unknown public key, import it with `gpg --recv-keys`".to_string())
+        );
+
+        // gpg still prints VALIDSIG for a signature made with a key that expired since
+        let expired = ["87CA9F79500EF0C6D957D5D32261F6E6104D70CA".to_string()];
+
+        assert_eq!(
+            check_status(EXPKEYSIG, &expired),
+            Some("signed with an expired or revoked key".to_string())
+        );
+        assert_eq!(
+            check_status("", &valid),
+            Some("no valid signature".to_string())
+        );
+    }
+
+    // a throwaway GnuPG home, its agent is stopped when it goes out of scope
+    struct Keyring {
+        home: PathBuf,
+    }
+
+    impl Keyring {
+        fn new(dir: &Path, name: &str) -> Keyring {
+            let home = dir.join(name);
+            fs::create_dir_all(&home).unwrap();
+            fs::set_permissions(&home, std::os::unix::fs::PermissionsExt::from_mode(0o700))
+                .unwrap();
+
+            Keyring { home }
+        }
+
+        fn gpg(&self, args: &[&str]) -> String {
+            let output = Command::new("gpg")
+                .arg("--homedir")
+                .arg(&self.home)
+                .args(["--batch", "--pinentry-mode", "loopback", "--passphrase", ""])
+                .args(args)
+                .stderr(Stdio::null())
+                .output()
+                .unwrap();
+
+            assert!(output.status.success(), "gpg {args:?} failed");
+
+            String::from_utf8_lossy(&output.stdout).to_string()
+        }
+
+        // a certify-only primary key with a signing subkey, returns the primary fingerprint
+        fn generate(&self, uid: &str) -> String {
+            self.gpg(&["--quick-gen-key", uid, "ed25519", "cert", "never"]);
+
+            let fingerprint = self
+                .gpg(&["--with-colons", "--list-keys"])
+                .lines()
+                .find_map(|line| line.strip_prefix("fpr:"))
+                .map(|line| line.trim_matches(':').to_string())
+                .unwrap();
+
+            self.gpg(&["--quick-add-key", &fingerprint, "ed25519", "sign", "never"]);
+
+            fingerprint
+        }
+
+        fn home(&self) -> &str {
+            self.home.to_str().unwrap()
+        }
+    }
+
+    impl Drop for Keyring {
+        fn drop(&mut self) {
+            let _ = Command::new("gpgconf")
+                .arg("--homedir")
+                .arg(&self.home)
+                .args(["--kill", "gpg-agent"])
+                .status();
+        }
+    }
+
+    #[test]
+    fn garbage_signature_over_a_large_tarball() {
+        if Command::new("gpg").arg("--version").output().is_err() {
+            eprintln!("gpg isn't installed, skipping");
+            return;
+        }
+
+        let dir =
+            std::env::temp_dir().join(format!("uvi-signatures-garbage-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let keyring = Keyring::new(&dir, "empty");
+
+        // an html error page saved as the signature, over more than a pipe buffer of data
+        fs::write(dir.join("foo-1.0.tar.sign"), "<html>404 Not Found</html>\n").unwrap();
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        for line in 0..200_000 {
+            writeln!(encoder, "line {line}").unwrap();
+        }
+        fs::write(dir.join("foo-1.0.tar.gz"), encoder.finish().unwrap()).unwrap();
+
+        let status = gpg_verify(
+            &dir.join("foo-1.0.tar.sign"),
+            &dir.join("foo-1.0.tar.gz"),
+            Some("gzip"),
+            Some(keyring.home()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            check_status(&status, &[]),
+            Some("no valid signature".to_string())
+        );
+
+        drop(keyring);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gpg_home_round_trip() {
+        if Command::new("gpg").arg("--version").output().is_err() {
+            eprintln!("gpg isn't installed, skipping");
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("uvi-signatures-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let keyring = Keyring::new(&dir, "signer");
+        let fingerprint = keyring.generate("Signer <signer@example.org>");
+
+        // a plain file with a .sig, and a tarball signed uncompressed like kernel.org does
+        let tarball = b"not really a tarball\n";
+        fs::write(dir.join("notes.txt"), "hello\n").unwrap();
+        fs::write(dir.join("foo-1.0.tar"), tarball).unwrap();
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(tarball).unwrap();
+        fs::write(dir.join("foo-1.0.tar.gz"), encoder.finish().unwrap()).unwrap();
+
+        for (file, signature) in [
+            ("notes.txt", "notes.txt.sig"),
+            ("foo-1.0.tar", "foo-1.0.tar.sign"),
+        ] {
+            keyring.gpg(&[
+                "--detach-sign",
+                "-o",
+                dir.join(signature).to_str().unwrap(),
+                dir.join(file).to_str().unwrap(),
+            ]);
+        }
+
+        fs::remove_file(dir.join("foo-1.0.tar")).unwrap();
+
+        let sources: Vec<Source> = [
+            "notes.txt",
+            "notes.txt.sig",
+            "foo-1.0.tar.gz",
+            "foo-1.0.tar.sign",
+        ]
+        .iter()
+        .map(|entry| Source::parse(entry))
+        .collect();
+        let fetched: Vec<PathBuf> = sources.iter().map(|source| source.location(&dir)).collect();
+
+        let pkgbuild = |keys: &str| parser::parse(&format!("validpgpkeys=({keys})"));
+
+        assert!(
+            verify(
+                &pkgbuild(&fingerprint),
+                &sources,
+                &fetched,
+                Some(keyring.home())
+            )
+            .is_ok()
+        );
+
+        // signed by a key the PKGBUILD doesn't list
+        let other = "ABAF11C65A2970B130ABE3C479BE3E4300411886";
+        assert!(verify(&pkgbuild(other), &sources, &fetched, Some(keyring.home())).is_err());
+
+        // a keyring that doesn't have the key
+        let empty = Keyring::new(&dir, "empty");
+        assert!(
+            verify(
+                &pkgbuild(&fingerprint),
+                &sources,
+                &fetched,
+                Some(empty.home())
+            )
+            .is_err()
+        );
+
+        // the file changed after it was signed
+        fs::write(dir.join("notes.txt"), "hellO\n").unwrap();
+        assert!(
+            verify(
+                &pkgbuild(&fingerprint),
+                &sources,
+                &fetched,
+                Some(keyring.home())
+            )
+            .is_err()
+        );
+
+        drop((keyring, empty));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
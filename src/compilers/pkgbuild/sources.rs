@@ -0,0 +1,243 @@
+// Everything in source=() (and source_$CARCH=()), fetched into the PKGBUILD dir
+// and then linked or extracted into $srcdir, the same layout makepkg uses.
+
+use super::parser::ParseResult;
+use crate::{
+    archive, fetch,
+    git::{self, GitUrl, Reference, Strategy},
+};
+use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceKind {
+    Local,
+    Remote,
+    Git,
+}
+
+#[derive(Debug, Clone)]
+pub struct Source {
+    // file (or directory) name inside $srcdir
+    pub name: String,
+    pub url: String,
+    pub kind: SourceKind,
+}
+
+impl Source {
+    pub fn parse(entry: &str) -> Source {
+        let (alias, url) = match entry.split_once("::") {
+            Some((alias, url)) => (Some(alias), url),
+            None => (None, entry),
+        };
+
+        let protocol = url.split_once("://").map(|(protocol, _)| protocol);
+
+        let (kind, url) = match protocol {
+            Some(p) if p == "git" || p.starts_with("git+") => {
+                (SourceKind::Git, url.trim_start_matches("git+"))
+            }
+            Some("file") => (SourceKind::Local, url.trim_start_matches("file://")),
+            Some(_) => (SourceKind::Remote, url),
+            None => (SourceKind::Local, url),
+        };
+
+        let name = match alias {
+            Some(alias) => alias.to_string(),
+            None => {
+                let path = url.split(['#', '?']).next().unwrap_or(url);
+                let base = path
+                    .trim_end_matches('/')
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or(path);
+
+                if kind == SourceKind::Git {
+                    base.trim_end_matches(".git").to_string()
+                } else {
+                    base.to_string()
+                }
+            }
+        };
+
+        Source {
+            name,
+            url: url.to_string(),
+            kind,
+        }
+    }
+
+    // where the fetched copy lives, local files may sit in a subdir of the PKGBUILD dir
+    pub fn location(&self, startdir: &Path) -> PathBuf {
+        match self.kind {
+            SourceKind::Local => startdir.join(&self.url),
+            _ => startdir.join(&self.name),
+        }
+    }
+}
+
+pub fn sources(result: &ParseResult) -> Vec<Source> {
+    result
+        .arch_array("source", std::env::consts::ARCH)
+        .iter()
+        .map(|entry| Source::parse(entry))
+        .collect()
+}
+
+// gets every source into `startdir`, returns where each one ended up
+pub fn fetch_all(
+    sources: &[Source],
+    startdir: &Path,
+) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut fetched = vec![];
+
+    for source in sources {
+        let destination = source.location(startdir);
+
+        match source.kind {
+            SourceKind::Local => {
+                if !destination.exists() {
+                    return Err(format!("local source {} not found", source.name).into());
+                }
+
+                println!("=> \x1b[1mINFO:\x1b[0m Found {}", source.name);
+            }
+            SourceKind::Remote => {
+                if destination.exists() {
+                    println!(
+                        "=> \x1b[1mINFO:\x1b[0m Found {}, not downloading it again",
+                        source.name
+                    );
+                } else {
+                    println!("=> \x1b[33;1mTRY:\x1b[0m Downloading {}..", source.url);
+
+                    fetch(&source.url, &destination)?;
+                }
+            }
+            SourceKind::Git => {
+                let git_url = GitUrl::parse(&source.url);
+
+                // a tag is a ref tip so its last commit is enough, anything else
+                // may be a -git package whose pkgver() counts or describes history
+                let default = match git_url.reference {
+                    Reference::Tag(_) => Strategy::Shallow,
+                    _ => Strategy::Blobless,
+                };
+
+                git::mirror(&git_url.url, &destination, default)?;
+            }
+        }
+
+        fetched.push(destination);
+    }
+
+    Ok(fetched)
+}
+
+// puts everything into $srcdir, unpacking archives unless they're in noextract=()
+pub fn extract_all(
+    sources: &[Source],
+    startdir: &Path,
+    srcdir: &Path,
+    noextract: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    fs::create_dir_all(srcdir)?;
+
+    for source in sources {
+        let fetched = source.location(startdir);
+        let target = srcdir.join(&source.name);
+
+        if target.symlink_metadata().is_ok() {
+            if target.is_dir() && !target.is_symlink() {
+                fs::remove_dir_all(&target)?;
+            } else {
+                fs::remove_file(&target)?;
+            }
+        }
+
+        match source.kind {
+            SourceKind::Local => {
+                fs::copy(&fetched, &target)?;
+            }
+            SourceKind::Remote => {
+                symlink(fs::canonicalize(&fetched)?, &target)?;
+            }
+            SourceKind::Git => {
+                git::clone_mirror(&fetched, &target, &GitUrl::parse(&source.url).reference)?;
+                continue;
+            }
+        }
+
+        // a tarball shipped next to the PKGBUILD is unpacked like a downloaded one
+        if !noextract.contains(&source.name) {
+            archive::unpack(&target, srcdir)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::GzEncoder};
+
+    fn tarball(path: &Path, name: &str, content: &str) {
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            fs::File::create(path).unwrap(),
+            Compression::default(),
+        ));
+        let mut header = tar::Header::new_gnu();
+
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, name, content.as_bytes())
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn local_archives_are_extracted_unless_noextract() {
+        let startdir =
+            std::env::temp_dir().join(format!("uvi-sources-local-{}", std::process::id()));
+        let srcdir = startdir.join("src");
+
+        fs::create_dir_all(startdir.join("patches")).unwrap();
+        tarball(
+            &startdir.join("vendor.tar.gz"),
+            "vendor/lib.rs",
+            "fn main() {}",
+        );
+        tarball(&startdir.join("keep.tar.gz"), "keep/lib.rs", "");
+        fs::write(startdir.join("patches/fix.patch"), "--- a\n+++ b\n").unwrap();
+
+        let sources: Vec<Source> = ["vendor.tar.gz", "file://keep.tar.gz", "patches/fix.patch"]
+            .iter()
+            .map(|entry| Source::parse(entry))
+            .collect();
+
+        extract_all(&sources, &startdir, &srcdir, &["keep.tar.gz".to_string()]).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(srcdir.join("vendor/lib.rs")).unwrap(),
+            "fn main() {}"
+        );
+        assert!(srcdir.join("vendor.tar.gz").exists());
+        assert!(srcdir.join("keep.tar.gz").exists());
+        assert!(!srcdir.join("keep").exists());
+        // anything that isn't an archive is just copied
+        assert!(srcdir.join("fix.patch").exists());
+
+        // running it again starts from a clean $srcdir entry
+        extract_all(&sources, &startdir, &srcdir, &[]).unwrap();
+
+        assert!(srcdir.join("keep/lib.rs").exists());
+
+        fs::remove_dir_all(&startdir).unwrap();
+    }
+}
@@ -0,0 +1,188 @@
+// Checks a staged package against the database and the filesystem before it gets merged.
+
+use crate::database::{self, Package};
+use regex::Regex;
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+};
+
+#[derive(Debug)]
+pub struct Conflict {
+    pub path: String,
+    pub owner: Option<String>,
+}
+
+// returns the conflicts that --overwrite allowed, errors out if any are left
+pub fn check(
+    pkg: &Package,
+    overwrite: &[String],
+) -> Result<Vec<Conflict>, Box<dyn std::error::Error>> {
+    let patterns: Vec<Regex> = overwrite
+        .iter()
+        .map(|glob| glob_to_regex(glob))
+        .collect::<Result<_, _>>()?;
+
+    let previous: HashSet<String> = database::load(&pkg.name)
+        .map(|old| old.files.into_iter().collect())
+        .unwrap_or_default();
+
+    let mut owners: HashMap<String, String> = HashMap::new();
+    for other in database::list() {
+        if other.name == pkg.name {
+            continue;
+        }
+
+        for file in other.files {
+            owners.insert(file, other.name.clone());
+        }
+    }
+
+    let mut allowed = vec![];
+    let mut blocking = vec![];
+
+    for file in &pkg.files {
+        let owner = owners.get(file).cloned();
+
+        let untracked =
+            owner.is_none() && !previous.contains(file) && fs::symlink_metadata(file).is_ok();
+
+        if owner.is_none() && !untracked {
+            continue;
+        }
+
+        let conflict = Conflict {
+            path: file.clone(),
+            owner,
+        };
+
+        if patterns.iter().any(|re| re.is_match(file)) {
+            allowed.push(conflict);
+        } else {
+            blocking.push(conflict);
+        }
+    }
+
+    if !blocking.is_empty() {
+        for conflict in &blocking {
+            match &conflict.owner {
+                Some(owner) => println!(
+                    "=> \x1b[31;1mERR:\x1b[0m {}: {} exists in filesystem (owned by {owner})",
+                    pkg.name, conflict.path
+                ),
+                None => println!(
+                    "=> \x1b[31;1mERR:\x1b[0m {}: {} exists in filesystem",
+                    pkg.name, conflict.path
+                ),
+            }
+        }
+
+        return Err(format!(
+            "{} conflicting files, use --overwrite <glob> to replace them",
+            blocking.len()
+        )
+        .into());
+    }
+
+    for conflict in &allowed {
+        println!("=> \x1b[1mINFO:\x1b[0m Overwriting {}", conflict.path);
+    }
+
+    Ok(allowed)
+}
+
+// drops overwritten files from the manifests of their previous owners
+pub fn transfer(
+    new_owner: &str,
+    overwritten: &[Conflict],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut by_owner: HashMap<&str, HashSet<&str>> = HashMap::new();
+
+    for conflict in overwritten {
+        if let Some(owner) = &conflict.owner {
+            by_owner.entry(owner).or_default().insert(&conflict.path);
+        }
+    }
+
+    for (owner, paths) in by_owner {
+        let mut old = match database::load(owner) {
+            Some(old) => old,
+            None => continue,
+        };
+
+        old.files.retain(|file| !paths.contains(&file.as_str()));
+        old.save()?;
+
+        for path in paths {
+            println!("=> \x1b[1mINFO:\x1b[0m {path} now owned by {new_owner} (was {owner})");
+        }
+    }
+
+    Ok(())
+}
+
+// pacman style globs, `*` also matches across `/`
+fn glob_to_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let mut pattern = String::from("^");
+    let mut in_class = false;
+
+    for c in glob.chars() {
+        match c {
+            '*' if !in_class => pattern.push_str(".*"),
+            '?' if !in_class => pattern.push('.'),
+            '[' if !in_class => {
+                in_class = true;
+                pattern.push('[');
+            }
+            ']' if in_class => {
+                in_class = false;
+                pattern.push(']');
+            }
+            '!' if in_class && pattern.ends_with('[') => pattern.push('^'),
+            // ranges like [0-9] go through as they are, `&&` and `~~` are set operations
+            // inside a regex class so those get escaped along with `\` and `[`
+            '\\' | '[' | '&' | '~' if in_class => {
+                pattern.push('\\');
+                pattern.push(c);
+            }
+            _ if in_class => pattern.push(c),
+            _ => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    pattern.push('$');
+
+    Regex::new(&pattern)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overwrite_globs() {
+        let cases = [
+            ("/usr/lib/libfoo[0-9].so", "/usr/lib/libfoo3.so", true),
+            ("/usr/lib/libfoo[0-9].so", "/usr/lib/libfoo-.so", false),
+            ("/usr/lib/libfoo[!0-9].so", "/usr/lib/libfooa.so", true),
+            ("/usr/lib/libfoo[!0-9].so", "/usr/lib/libfoo3.so", false),
+            ("/usr/lib/libfoo[a-c_].so", "/usr/lib/libfoo_.so", true),
+            ("/usr/share/foo[&~].txt", "/usr/share/foo~.txt", true),
+            ("/usr/share/foo[&~].txt", "/usr/share/foo&.txt", true),
+            ("/usr/bin/*", "/usr/bin/foo", true),
+            ("/usr/*", "/usr/share/doc/foo/README", true),
+            ("/usr/bin/fo?", "/usr/bin/foo", true),
+            ("/usr/bin/fo?", "/usr/bin/fooo", false),
+            ("/usr/lib/libfoo.so.1", "/usr/lib/libfooxso.1", false),
+            ("/usr/lib/libfoo.so+", "/usr/lib/libfoo.so+", true),
+        ];
+
+        for (glob, path, expected) in cases {
+            assert_eq!(
+                glob_to_regex(glob).unwrap().is_match(path),
+                expected,
+                "{glob} against {path}"
+            );
+        }
+    }
+}
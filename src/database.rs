@@ -0,0 +1,143 @@
+// Installed package database, laid out like pacman's local db:
+// <db>/<name>/desc holds the metadata and <db>/<name>/files the manifest.
+
+use crate::fetch_env;
+use std::{fs, path::PathBuf};
+
+#[derive(Debug, Default, Clone)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub source: String,
+    pub build_system: String,
+    pub prefix: String,
+    // --bargs it was built with, an upgrade rebuilds it the same way
+    pub bargs: String,
+    pub files: Vec<String>,
+}
+
+impl Package {
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let entry = entry_path(&self.name);
+        fs::create_dir_all(&entry)?;
+
+        let desc = format!(
+            "%NAME%\n{}\n\n%VERSION%\n{}\n\n%SOURCE%\n{}\n\n%BUILDSYSTEM%\n{}\n\n%PREFIX%\n{}\n\n%BUILDARGS%\n{}\n",
+            self.name, self.version, self.source, self.build_system, self.prefix, self.bargs
+        );
+        let files = format!("%FILES%\n{}\n", self.files.join("\n"));
+
+        fs::write(entry.join("desc"), desc)?;
+        fs::write(entry.join("files"), files)?;
+
+        Ok(())
+    }
+}
+
+pub fn db_path() -> PathBuf {
+    fetch_env("DB")
+}
+
+fn entry_path(name: &str) -> PathBuf {
+    db_path().join(name)
+}
+
+// returns every value below a %SECTION% header until the next blank line
+fn section(content: &str, name: &str) -> Vec<String> {
+    let header = format!("%{name}%");
+
+    content
+        .lines()
+        .skip_while(|line| *line != header)
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+fn first(content: &str, name: &str) -> String {
+    section(content, name)
+        .into_iter()
+        .next()
+        .unwrap_or_default()
+}
+
+pub fn load(name: &str) -> Option<Package> {
+    let entry = entry_path(name);
+    let desc = fs::read_to_string(entry.join("desc")).ok()?;
+    let files = fs::read_to_string(entry.join("files")).unwrap_or_default();
+
+    Some(Package {
+        name: first(&desc, "NAME"),
+        version: first(&desc, "VERSION"),
+        source: first(&desc, "SOURCE"),
+        build_system: first(&desc, "BUILDSYSTEM"),
+        prefix: first(&desc, "PREFIX"),
+        bargs: first(&desc, "BUILDARGS"),
+        files: section(&files, "FILES"),
+    })
+}
+
+pub fn list() -> Vec<Package> {
+    let mut packages: Vec<Package> = match fs::read_dir(db_path()) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| load(&entry.file_name().to_string_lossy()))
+            .collect(),
+        Err(_) => vec![],
+    };
+
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages
+}
+
+pub fn remove(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let entry = entry_path(name);
+
+    if entry.exists() {
+        fs::remove_dir_all(entry)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip() {
+        let pkg = Package {
+            name: format!("uvi-test-db-{}", std::process::id()),
+            version: "1:2.0-3".to_string(),
+            source: "https://example.org/foo.git".to_string(),
+            build_system: "meson".to_string(),
+            prefix: "/opt/x".to_string(),
+            bargs: "-Dwayland=false -Dx11=true".to_string(),
+            files: vec![
+                "/opt/x/bin/foo".to_string(),
+                "/opt/x/lib/libfoo.so".to_string(),
+            ],
+        };
+
+        pkg.save().unwrap();
+
+        let loaded = load(&pkg.name).unwrap();
+
+        assert_eq!(loaded.version, pkg.version);
+        assert_eq!(loaded.prefix, pkg.prefix);
+        assert_eq!(loaded.bargs, pkg.bargs);
+        assert_eq!(loaded.files, pkg.files);
+
+        // entries written before %BUILDARGS% existed still load
+        let desc = entry_path(&pkg.name).join("desc");
+        let old = fs::read_to_string(&desc).unwrap();
+
+        fs::write(&desc, &old[..old.find("\n%BUILDARGS%").unwrap()]).unwrap();
+
+        assert_eq!(load(&pkg.name).unwrap().bargs, "");
+        assert_eq!(load(&pkg.name).unwrap().prefix, "/opt/x");
+
+        remove(&pkg.name).unwrap();
+    }
+}
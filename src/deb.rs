@@ -0,0 +1,505 @@
+// Debian packages: an ar archive holding debian-binary, control.tar.* (the metadata and
+// maintainer scripts) and data.tar.* (the files). Nothing is built, the payload goes
+// straight into the staging dir and is merged like any other package. Maintainer scripts
+// are never run.
+
+use crate::{
+    archive::{self, Format, ar},
+    database::Package,
+    dependencies::{self, Dependency},
+    staging,
+};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Read},
+    path::{Component, Path},
+};
+
+// Debian names of libraries Arch packages under another name, anything else is looked up as is
+const NAMES: &[(&str, &str)] = &[
+    ("libc6", "glibc"),
+    ("libgcc-s1", "gcc-libs"),
+    ("libstdc++6", "gcc-libs"),
+    ("zlib1g", "zlib"),
+    ("libssl3", "openssl"),
+    ("libssl3t64", "openssl"),
+    ("libbz2-1.0", "bzip2"),
+    ("liblzma5", "xz"),
+    ("libzstd1", "zstd"),
+    ("libexpat1", "expat"),
+    ("libffi8", "libffi"),
+    ("libpng16-16", "libpng"),
+    ("libpng16-16t64", "libpng"),
+    ("libfreetype6", "freetype2"),
+    ("libfontconfig1", "fontconfig"),
+    ("libglib2.0-0", "glib2"),
+    ("libglib2.0-0t64", "glib2"),
+    ("libgtk-3-0", "gtk3"),
+    ("libgtk-3-0t64", "gtk3"),
+    ("libx11-6", "libx11"),
+    ("libxcb1", "libxcb"),
+    ("libdbus-1-3", "dbus"),
+    ("libnss3", "nss"),
+    ("libasound2", "alsa-lib"),
+    ("libasound2t64", "alsa-lib"),
+    ("libcurl4", "curl"),
+    ("libcurl4t64", "curl"),
+    ("python3", "python"),
+];
+
+const SCRIPTS: [&str; 4] = ["preinst", "postinst", "prerm", "postrm"];
+
+#[derive(Debug, Default)]
+pub struct Control {
+    pub package: String,
+    pub version: String,
+    // each entry is a list of alternatives, `a | b` is satisfied by either
+    pub depends: Vec<Vec<Dependency>>,
+    pub scripts: Vec<String>,
+}
+
+// reads the control data and unpacks the payload into the package's staging dir
+pub fn stage(file: &Path, nodeps: bool, fast: bool) -> Result<Package, Box<dyn std::error::Error>> {
+    let control = control(file)?;
+
+    println!(
+        "=> \x1b[1mINFO:\x1b[0m Found {} {} (.deb)",
+        control.package, control.version
+    );
+
+    if !nodeps {
+        check_depends(&control, fast)?;
+    }
+
+    if !control.scripts.is_empty() {
+        println!(
+            "=> \x1b[1mINFO:\x1b[0m Not running the maintainer scripts {} ships ({})",
+            control.package,
+            control.scripts.join(", ")
+        );
+    }
+
+    let staged = staging::prepare(&control.package)?;
+
+    println!("=> \x1b[33;1mTRY:\x1b[0m Unpacking the payload..");
+
+    unpack_data(file, &staged)?;
+
+    Ok(Package {
+        name: control.package,
+        version: control.version,
+        build_system: "deb".to_string(),
+        // every path in a .deb is absolute, --prefix doesn't apply
+        prefix: "/".to_string(),
+        ..Default::default()
+    })
+}
+
+pub fn unpack_data(file: &Path, destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut found = false;
+
+    ar::read_members(BufReader::new(File::open(file)?), |member, data| {
+        if let Some(Format::Tar(compression)) = member_format(member, "data.tar") {
+            found = true;
+            archive::extract_tar(archive::decompress(data, compression)?, destination, true)?;
+        }
+
+        Ok(())
+    })?;
+
+    if !found {
+        return Err(format!("{} has no data.tar", file.display()).into());
+    }
+
+    Ok(())
+}
+
+pub fn control(file: &Path) -> Result<Control, Box<dyn std::error::Error>> {
+    let mut format_version = String::new();
+    let mut fields: Option<HashMap<String, String>> = None;
+    let mut scripts: Vec<String> = vec![];
+
+    ar::read_members(BufReader::new(File::open(file)?), |member, data| {
+        if member == "debian-binary" {
+            data.read_to_string(&mut format_version)?;
+        } else if let Some(Format::Tar(compression)) = member_format(member, "control.tar") {
+            let mut control_tar = tar::Archive::new(archive::decompress(data, compression)?);
+
+            for entry in control_tar.entries()? {
+                let mut entry = entry?;
+                let path = entry.path()?.to_path_buf();
+
+                // ./control, ./postinst.. nothing in there is nested
+                let name = match path.components().next_back() {
+                    Some(Component::Normal(name)) => name.to_string_lossy().to_string(),
+                    _ => continue,
+                };
+
+                if name == "control" {
+                    let mut content = String::new();
+                    entry.read_to_string(&mut content)?;
+
+                    fields = Some(parse_fields(&content));
+                } else if SCRIPTS.contains(&name.as_str()) {
+                    scripts.push(name);
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+
+    if !format_version.starts_with("2.") {
+        return Err(format!(
+            "{} is a version {:?} .deb, only 2.x is supported",
+            file.display(),
+            format_version.trim()
+        )
+        .into());
+    }
+
+    let fields = fields.ok_or_else(|| format!("{} has no control file", file.display()))?;
+
+    let field = |name: &str| fields.get(name).cloned().unwrap_or_default();
+
+    let package = field("Package");
+    let version = field("Version");
+
+    if package.is_empty() || version.is_empty() {
+        return Err(format!("{} has no Package or Version", file.display()).into());
+    }
+
+    // Pre-Depends only differ in when dpkg needs them, before unpacking
+    let depends = [field("Pre-Depends"), field("Depends")]
+        .iter()
+        .flat_map(|entries| parse_depends(entries))
+        .collect();
+
+    Ok(Control {
+        package,
+        version,
+        depends,
+        scripts,
+    })
+}
+
+// data.tar.xz, control.tar.zst, data.tar.. whatever `prefix` is compressed with
+fn member_format(member: &str, prefix: &str) -> Option<Format> {
+    if member.starts_with(prefix) {
+        Format::from_name(member)
+    } else {
+        None
+    }
+}
+
+// `Field: value` lines, a line starting with whitespace continues the previous field
+fn parse_fields(content: &str) -> HashMap<String, String> {
+    let mut fields: HashMap<String, String> = HashMap::new();
+    let mut last: Option<String> = None;
+
+    for line in content.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some(value) = last.as_ref().and_then(|name| fields.get_mut(name)) {
+                value.push('\n');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            fields.insert(name.trim().to_string(), value.trim().to_string());
+            last = Some(name.trim().to_string());
+        }
+    }
+
+    fields
+}
+
+// `libc6 (>= 2.34), libfoo1 | libbar1, python3:any` -> dependencies uvi can check
+fn parse_depends(entries: &str) -> Vec<Vec<Dependency>> {
+    entries
+        .split(',')
+        .map(|group| {
+            group
+                .split('|')
+                .map(|alternative| parse_alternative(alternative.trim()))
+                .filter(|dependency| !dependency.name.is_empty())
+                .collect::<Vec<Dependency>>()
+        })
+        .filter(|group| !group.is_empty())
+        .collect()
+}
+
+fn parse_alternative(alternative: &str) -> Dependency {
+    // drop [amd64] architecture and <!nocheck> build profile restrictions, both come after
+    // the version, which can have a `<` of its own
+    let end = alternative
+        .find(')')
+        .map(|close| close + 1)
+        .or_else(|| alternative.find(['[', '<']))
+        .unwrap_or(alternative.len());
+    let alternative = alternative[..end].trim();
+
+    let (name, constraint) = match alternative.split_once('(') {
+        Some((name, constraint)) => (name.trim(), constraint.trim_end_matches(')').trim()),
+        None => (alternative, ""),
+    };
+
+    // python3:any, libfoo:amd64
+    let name = name.split(':').next().unwrap_or(name);
+    let name = NAMES
+        .iter()
+        .find(|(debian, _)| *debian == name)
+        .map(|(_, arch)| *arch)
+        .unwrap_or(name);
+
+    // dpkg spells strictly less and greater as << and >>
+    let constraint = constraint
+        .replace("<<", "<")
+        .replace(">>", ">")
+        .replace(' ', "");
+
+    Dependency::parse(&format!("{name}{constraint}"))
+}
+
+fn check_depends(control: &Control, fast: bool) -> Result<(), Box<dyn std::error::Error>> {
+    println!("=> \x1b[33;1mTRY:\x1b[0m Checking dependencies..");
+
+    let missing: Vec<String> = control
+        .depends
+        .iter()
+        .filter(|group| !group.iter().any(dependencies::satisfied))
+        .map(|group| {
+            group
+                .iter()
+                .map(|dependency| dependency.raw.as_str())
+                .collect::<Vec<&str>>()
+                .join(" | ")
+        })
+        .collect();
+
+    dependencies::confirm_missing(&control.package, &missing, fast)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dependencies::Operator;
+    use std::{fs, io::Write, os::unix::fs::PermissionsExt, path::PathBuf};
+
+    const CONTROL: &str = "Package: hello
+Version: 2.10-3
+Architecture: amd64
+Maintainer: Santiago Vila <sanvila@debian.org>
+Pre-Depends: dpkg (>= 1.19.1)
+Depends: libc6 (>= 2.34), libfoo1 (<< 3) | libbar1 [amd64], python3:any <!nocheck>
+Description: example package based on GNU hello
+ The GNU hello program produces a familiar, friendly greeting.
+";
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uvi-deb-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    // (name, mode, content or symlink target, is a symlink)
+    fn tar(entries: &[(&str, u32, &str, bool)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+
+        for (name, mode, content, is_symlink) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(*mode);
+
+            if *is_symlink {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_size(0);
+                builder.append_link(&mut header, name, content).unwrap();
+            } else if name.ends_with('/') {
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_size(0);
+                builder.append_data(&mut header, name, &[][..]).unwrap();
+            } else {
+                header.set_size(content.len() as u64);
+                builder
+                    .append_data(&mut header, name, content.as_bytes())
+                    .unwrap();
+            }
+        }
+
+        builder.into_inner().unwrap()
+    }
+
+    fn ar(members: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = ar::MAGIC.to_vec();
+
+        for (name, data) in members {
+            let header = format!(
+                "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+                name,
+                0,
+                0,
+                0,
+                100644,
+                data.len()
+            );
+
+            bytes.extend(header.as_bytes());
+            bytes.extend(data);
+
+            if data.len() % 2 == 1 {
+                bytes.push(b'\n');
+            }
+        }
+
+        bytes
+    }
+
+    fn xz(data: &[u8]) -> Vec<u8> {
+        let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn hello_deb(dir: &Path, data_tar: Vec<u8>) -> PathBuf {
+        let control_tar = tar(&[
+            ("./", 0o755, "", false),
+            ("./control", 0o644, CONTROL, false),
+            ("./postinst", 0o755, "#!/bin/sh\nexit 0\n", false),
+        ]);
+
+        let path = dir.join("hello_2.10-3_amd64.deb");
+
+        fs::write(
+            &path,
+            ar(&[
+                ("debian-binary", b"2.0\n".to_vec()),
+                ("control.tar.xz", xz(&control_tar)),
+                ("data.tar.zst", zstd::encode_all(&data_tar[..], 0).unwrap()),
+            ]),
+        )
+        .unwrap();
+
+        path
+    }
+
+    #[test]
+    fn reads_control_from_control_tar_xz() {
+        let dir = scratch("control");
+        let deb = hello_deb(&dir, tar(&[]));
+
+        let control = control(&deb).unwrap();
+
+        assert_eq!(control.package, "hello");
+        assert_eq!(control.version, "2.10-3");
+        assert_eq!(control.scripts, ["postinst"]);
+
+        let depends: Vec<Vec<&str>> = control
+            .depends
+            .iter()
+            .map(|group| group.iter().map(|dep| dep.raw.as_str()).collect())
+            .collect();
+
+        assert_eq!(
+            depends,
+            [
+                vec!["dpkg>=1.19.1"],
+                vec!["glibc>=2.34"],
+                vec!["libfoo1<3", "libbar1"],
+                vec!["python"],
+            ]
+        );
+        assert_eq!(
+            control.depends[2][0].constraint,
+            Some((Operator::Less, "3".to_string()))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn unpacks_data_tar_zst() {
+        let dir = scratch("data");
+        let destination = dir.join("staged");
+        fs::create_dir(&destination).unwrap();
+
+        let deb = hello_deb(
+            &dir,
+            tar(&[
+                ("./", 0o755, "", false),
+                ("./usr/", 0o755, "", false),
+                ("./usr/bin/", 0o755, "", false),
+                ("./usr/bin/hello", 0o755, "#!/bin/sh\necho hello\n", false),
+                ("./usr/lib/", 0o755, "", false),
+                ("./usr/lib/chrome-sandbox", 0o4755, "", false),
+                ("./usr/lib/shared/", 0o3775, "", false),
+                // only resolves once merged into /, fine in a package payload
+                ("./usr/bin/hi", 0o777, "/usr/bin/hello", true),
+            ]),
+        );
+
+        unpack_data(&deb, &destination).unwrap();
+
+        let hello = destination.join("usr/bin/hello");
+
+        assert_eq!(
+            fs::read_to_string(&hello).unwrap(),
+            "#!/bin/sh\necho hello\n"
+        );
+        assert_eq!(
+            fs::metadata(&hello).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+        let mode = |path: &str| {
+            fs::metadata(destination.join(path))
+                .unwrap()
+                .permissions()
+                .mode()
+        };
+
+        assert_eq!(mode("usr/lib/chrome-sandbox") & 0o7777, 0o4755);
+        assert_eq!(mode("usr/lib/shared") & 0o7777, 0o3775);
+        assert_eq!(
+            fs::read_link(destination.join("usr/bin/hi")).unwrap(),
+            Path::new("/usr/bin/hello")
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_escaping_payloads() {
+        let dir = scratch("escape");
+        let destination = dir.join("staged");
+        fs::create_dir(&destination).unwrap();
+
+        // tar::Builder won't write `..` itself
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+        header.set_size(1);
+        header.set_mode(0o644);
+        header.set_cksum();
+
+        let mut builder = tar::Builder::new(vec![]);
+        builder.append(&header, &b"x"[..]).unwrap();
+
+        let deb = hello_deb(&dir, builder.into_inner().unwrap());
+
+        assert!(unpack_data(&deb, &destination).is_err());
+        assert!(!dir.join("escape").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_other_format_versions() {
+        let dir = scratch("version");
+        let path = dir.join("old.deb");
+
+        fs::write(&path, ar(&[("debian-binary", b"0.939000\n".to_vec())])).unwrap();
+
+        assert!(control(&path).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,601 @@
+// Works out what has to be built before a package: everything in depends=(), makedepends=()
+// and checkdepends=() that isn't already installed gets cloned and visited the same way,
+// and the build order comes out of a depth first walk (dependencies first).
+// Meson projects only get a warning for what pkg-config can't find, there's nothing to clone
+// for those and meson has its own ways to look (cmake, config tools), so setup decides.
+
+use crate::{
+    clone_package,
+    compilers::{
+        meson,
+        pkgbuild::{self, parser::ParseResult},
+    },
+    database,
+    version::Version,
+};
+use rprompt::prompt_reply;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Less,
+    LessEqual,
+    Equal,
+    GreaterEqual,
+    Greater,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DependencyKind {
+    // a package name from a PKGBUILD, can be fetched and built
+    Package,
+    // a pkg-config module from meson, can only be reported
+    PkgConfig,
+}
+
+#[derive(Debug, Clone)]
+pub struct Dependency {
+    pub name: String,
+    pub constraint: Option<(Operator, String)>,
+    // as written in the PKGBUILD, e.g. `foo>=1.2`
+    pub raw: String,
+    pub kind: DependencyKind,
+}
+
+impl Dependency {
+    pub fn parse(entry: &str) -> Dependency {
+        // `foo>=1.2: optional description` is only used by optdepends, cut it anyway
+        let raw = entry.split(": ").next().unwrap_or(entry).trim();
+
+        let operators = [
+            (">=", Operator::GreaterEqual),
+            ("<=", Operator::LessEqual),
+            ("=", Operator::Equal),
+            (">", Operator::Greater),
+            ("<", Operator::Less),
+        ];
+
+        for (symbol, operator) in operators {
+            if let Some((name, version)) = raw.split_once(symbol) {
+                return Dependency {
+                    name: name.trim().to_string(),
+                    constraint: Some((operator, version.trim().to_string())),
+                    raw: raw.to_string(),
+                    kind: DependencyKind::Package,
+                };
+            }
+        }
+
+        Dependency {
+            name: raw.to_string(),
+            constraint: None,
+            raw: raw.to_string(),
+            kind: DependencyKind::Package,
+        }
+    }
+
+    // meson gives the name and each version constraint separately, e.g. `>= 2.56`
+    pub fn pkg_config(name: &str, version: Option<&str>) -> Dependency {
+        let raw = match version {
+            Some(version) => format!("{name}{}", version.replace(' ', "")),
+            None => name.to_string(),
+        };
+
+        Dependency {
+            kind: DependencyKind::PkgConfig,
+            ..Dependency::parse(&raw)
+        }
+    }
+
+    pub fn matches(&self, version: &str) -> bool {
+        let (operator, wanted) = match &self.constraint {
+            Some(constraint) => constraint,
+            None => return true,
+        };
+
+        // `foo>=1.2` doesn't care about the pkgrel, `foo>=1.2-3` does
+        let ordering = Version::from(version).cmp(&Version::from(wanted.as_str()));
+
+        match operator {
+            Operator::Less => ordering.is_lt(),
+            Operator::LessEqual => ordering.is_le(),
+            Operator::Equal => ordering.is_eq(),
+            Operator::GreaterEqual => ordering.is_ge(),
+            Operator::Greater => ordering.is_gt(),
+        }
+    }
+}
+
+// something to install, dependencies come before whatever needs them
+#[derive(Debug, Clone)]
+pub struct Build {
+    pub name: String,
+    pub dir: PathBuf,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Visiting,
+    Done,
+}
+
+pub struct Resolver {
+    repo: String,
+    nocheck: bool,
+    gpg_home: Option<String>,
+    states: HashMap<String, State>,
+    stack: Vec<String>,
+    order: Vec<Build>,
+}
+
+impl Resolver {
+    pub fn new(repo: &str, nocheck: bool, gpg_home: Option<&str>) -> Resolver {
+        Resolver {
+            repo: repo.to_string(),
+            nocheck,
+            gpg_home: gpg_home.map(|home| home.to_string()),
+            states: HashMap::new(),
+            stack: vec![],
+            order: vec![],
+        }
+    }
+
+    // everything that has to be installed for `name`, in build order, `name` itself last
+    pub fn resolve(
+        mut self,
+        name: &str,
+        dir: &Path,
+        source: &str,
+    ) -> Result<Vec<Build>, Box<dyn std::error::Error>> {
+        self.visit(name, dir, source)?;
+
+        Ok(self.order)
+    }
+
+    fn visit(
+        &mut self,
+        name: &str,
+        dir: &Path,
+        source: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.states.insert(name.to_string(), State::Visiting);
+        self.stack.push(name.to_string());
+
+        for dependency in self.missing(dir)? {
+            // llvm, boost, mpi.. are found through cmake or config tools, not pkg-config
+            if dependency.kind == DependencyKind::PkgConfig {
+                println!(
+                    "=> \x1b[1mINFO:\x1b[0m {name} needs {} but pkg-config can't find it, leaving it to meson",
+                    dependency.raw
+                );
+
+                continue;
+            }
+
+            match self.states.get(&dependency.name) {
+                Some(State::Done) => continue,
+                Some(State::Visiting) => {
+                    let start = self
+                        .stack
+                        .iter()
+                        .position(|visiting| *visiting == dependency.name)
+                        .unwrap_or(0);
+
+                    let mut cycle = self.stack[start..].to_vec();
+                    cycle.push(dependency.name.clone());
+
+                    return Err(format!("dependency cycle: {}", cycle.join(" -> ")).into());
+                }
+                None => {}
+            }
+
+            println!(
+                "=> \x1b[33;1mTRY:\x1b[0m Fetching dependency {} (needed by {name})..",
+                dependency.raw
+            );
+
+            let (dep_dir, dep_source) =
+                clone_package(&dependency.name, &self.repo, self.gpg_home.as_deref())?;
+            let pkgbuild_path = dep_dir.join("PKGBUILD");
+
+            if !pkgbuild_path.exists() {
+                return Err(format!("no PKGBUILD found for dependency {}", dependency.name).into());
+            }
+
+            let version = pkgbuild::full_version(&pkgbuild::load(&pkgbuild_path)?);
+
+            if !dependency.matches(&version) {
+                return Err(format!(
+                    "{name} needs {} but {} {version} is what's available",
+                    dependency.raw, dependency.name
+                )
+                .into());
+            }
+
+            self.visit(&dependency.name, &dep_dir, &dep_source)?;
+        }
+
+        self.stack.pop();
+        self.states.insert(name.to_string(), State::Done);
+        self.order.push(Build {
+            name: name.to_string(),
+            dir: dir.to_path_buf(),
+            source: source.to_string(),
+        });
+
+        Ok(())
+    }
+
+    // dependencies of the package in `dir` that nothing on the system satisfies yet
+    fn missing(&self, dir: &Path) -> Result<Vec<Dependency>, Box<dyn std::error::Error>> {
+        let (declared, own_names) = requirements(dir, self.nocheck)?;
+
+        let mut missing: Vec<Dependency> = vec![];
+
+        for dependency in declared {
+            if own_names.contains(&dependency.name)
+                || missing.iter().any(|other| other.name == dependency.name)
+            {
+                continue;
+            }
+
+            if satisfied(&dependency) {
+                continue;
+            }
+
+            missing.push(dependency);
+        }
+
+        Ok(missing)
+    }
+}
+
+// orders already fetched packages so that anything one of them needs from the others comes first
+pub fn sort(builds: Vec<Build>, nocheck: bool) -> Result<Vec<Build>, Box<dyn std::error::Error>> {
+    let mut provides: HashMap<String, usize> = HashMap::new();
+    let mut needs: Vec<Vec<String>> = vec![];
+
+    for (index, build) in builds.iter().enumerate() {
+        let (declared, own_names) = requirements(&build.dir, nocheck)?;
+
+        provides.insert(build.name.clone(), index);
+        for own_name in own_names {
+            provides.insert(own_name, index);
+        }
+
+        needs.push(
+            declared
+                .into_iter()
+                .map(|dependency| dependency.name)
+                .collect(),
+        );
+    }
+
+    fn visit(
+        index: usize,
+        builds: &[Build],
+        needs: &[Vec<String>],
+        provides: &HashMap<String, usize>,
+        states: &mut HashMap<usize, State>,
+        order: &mut Vec<usize>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match states.get(&index) {
+            Some(State::Done) => return Ok(()),
+            Some(State::Visiting) => {
+                return Err(format!("dependency cycle involving {}", builds[index].name).into());
+            }
+            None => {}
+        }
+
+        states.insert(index, State::Visiting);
+
+        for name in &needs[index] {
+            if let Some(&other) = provides.get(name)
+                && other != index
+            {
+                visit(other, builds, needs, provides, states, order)?;
+            }
+        }
+
+        states.insert(index, State::Done);
+        order.push(index);
+
+        Ok(())
+    }
+
+    let mut states: HashMap<usize, State> = HashMap::new();
+    let mut order: Vec<usize> = vec![];
+
+    for index in 0..builds.len() {
+        visit(index, &builds, &needs, &provides, &mut states, &mut order)?;
+    }
+
+    Ok(order
+        .into_iter()
+        .map(|index| builds[index].clone())
+        .collect())
+}
+
+// what the package in `dir` declares it needs, and the names it installs itself
+fn requirements(
+    dir: &Path,
+    nocheck: bool,
+) -> Result<(Vec<Dependency>, Vec<String>), Box<dyn std::error::Error>> {
+    let pkgbuild_path = dir.join("PKGBUILD");
+
+    if pkgbuild_path.exists() {
+        let result = pkgbuild::load(&pkgbuild_path)?;
+
+        Ok((declared(&result, nocheck), result.array("pkgname")))
+    } else if dir.join("meson.build").exists() {
+        Ok((meson::dependencies(&dir.to_string_lossy()), vec![]))
+    } else {
+        Ok((vec![], vec![]))
+    }
+}
+
+fn declared(result: &ParseResult, nocheck: bool) -> Vec<Dependency> {
+    let arch = std::env::consts::ARCH;
+
+    let mut entries = result.arch_array("depends", arch);
+    entries.extend(result.arch_array("makedepends", arch));
+
+    if !nocheck {
+        entries.extend(result.arch_array("checkdepends", arch));
+    }
+
+    entries
+        .iter()
+        .map(|entry| Dependency::parse(entry))
+        .collect()
+}
+
+// installed by uvi, known to pacman, or at least visible to pkg-config
+pub fn satisfied(dependency: &Dependency) -> bool {
+    if dependency.kind == DependencyKind::PkgConfig {
+        return pkg_config_satisfied(dependency);
+    }
+
+    if database::load(&dependency.name).is_some_and(|pkg| dependency.matches(&pkg.version)) {
+        return true;
+    }
+
+    // pacman -T prints whatever isn't satisfied and exits 127
+    let pacman = Command::new("pacman")
+        .args(["-T", &dependency.raw])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    if pacman.is_ok_and(|status| status.success()) {
+        return true;
+    }
+
+    pkg_config_satisfied(dependency)
+}
+
+fn pkg_config_satisfied(dependency: &Dependency) -> bool {
+    let query = match &dependency.constraint {
+        Some((operator, version)) => {
+            let symbol = match operator {
+                Operator::Less => "<",
+                Operator::LessEqual => "<=",
+                Operator::Equal => "=",
+                Operator::GreaterEqual => ">=",
+                Operator::Greater => ">",
+            };
+
+            format!("{} {symbol} {version}", dependency.name)
+        }
+        None => dependency.name.clone(),
+    };
+
+    Command::new("pkg-config")
+        .args(["--exists", &query])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// prebuilt packages (.deb, .rpm) can't have their dependencies built, only reported
+pub fn confirm_missing(
+    package: &str,
+    missing: &[String],
+    fast: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    for dependency in missing {
+        println!(
+            "=> \x1b[31;1mERR:\x1b[0m {package} needs {dependency} but nothing installed provides it"
+        );
+    }
+
+    if fast {
+        return Ok(());
+    }
+
+    let reply = prompt_reply("=> \x1b[33;1mTRY:\x1b[0m Install anyway? [y/N]: ")?;
+
+    if reply != "y" {
+        return Err(
+            format!("{package} has missing dependencies, use --nodeps to skip this check").into(),
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parse_and_match() {
+        for (entry, name, constraint, matching, not_matching) in [
+            ("foo", "foo", None, "1.0-1", None),
+            (
+                "foo>=1.2",
+                "foo",
+                Some((Operator::GreaterEqual, "1.2")),
+                "1.2-1",
+                Some("1.1-9"),
+            ),
+            (
+                "foo<=2",
+                "foo",
+                Some((Operator::LessEqual, "2")),
+                "2-5",
+                Some("2.1-1"),
+            ),
+            (
+                "foo>1",
+                "foo",
+                Some((Operator::Greater, "1")),
+                "1.0.1-1",
+                Some("1-3"),
+            ),
+            (
+                "foo<3",
+                "foo",
+                Some((Operator::Less, "3")),
+                "2.9-1",
+                Some("3-1"),
+            ),
+            (
+                "foo=1:2.0-1",
+                "foo",
+                Some((Operator::Equal, "1:2.0-1")),
+                "1:2.0-1",
+                Some("2.0-1"),
+            ),
+            (
+                "foo=1:2.0-1",
+                "foo",
+                Some((Operator::Equal, "1:2.0-1")),
+                "1:2.0-1",
+                Some("1:2.0-2"),
+            ),
+            // the pkgrel only counts when the constraint has one
+            (
+                "foo=2.0",
+                "foo",
+                Some((Operator::Equal, "2.0")),
+                "2.0-7",
+                Some("2.0.1-1"),
+            ),
+            (
+                "foo>=1.2-3",
+                "foo",
+                Some((Operator::GreaterEqual, "1.2-3")),
+                "1.2-3",
+                Some("1.2-2"),
+            ),
+            // optdepends style descriptions are cut
+            (
+                "foo>=1.2: for the bar feature",
+                "foo",
+                Some((Operator::GreaterEqual, "1.2")),
+                "1.3-1",
+                Some("1.0-1"),
+            ),
+        ] {
+            let dependency = Dependency::parse(entry);
+
+            assert_eq!(dependency.name, name, "{entry}");
+            assert_eq!(
+                dependency.constraint,
+                constraint.map(|(operator, version)| (operator, version.to_string())),
+                "{entry}"
+            );
+            assert_eq!(dependency.kind, DependencyKind::Package);
+            assert!(dependency.matches(matching), "{entry} against {matching}");
+
+            if let Some(version) = not_matching {
+                assert!(!dependency.matches(version), "{entry} against {version}");
+            }
+        }
+
+        assert_eq!(Dependency::parse("foo>=1.2: for bar").raw, "foo>=1.2");
+    }
+
+    #[test]
+    fn pkg_config_constraints() {
+        let glib = Dependency::pkg_config("glib-2.0", Some(">= 2.56"));
+
+        assert_eq!(glib.name, "glib-2.0");
+        assert_eq!(glib.raw, "glib-2.0>=2.56");
+        assert_eq!(glib.kind, DependencyKind::PkgConfig);
+        assert_eq!(
+            glib.constraint,
+            Some((Operator::GreaterEqual, "2.56".to_string()))
+        );
+        assert!(glib.matches("2.78.1"));
+        assert!(!glib.matches("2.54"));
+
+        assert_eq!(Dependency::pkg_config("zlib", None).constraint, None);
+    }
+
+    // a dir with a PKGBUILD per (name, depends), in the order given
+    fn builds(name: &str, packages: &[(&str, &str)]) -> (PathBuf, Vec<Build>) {
+        let dir = std::env::temp_dir().join(format!("uvi-sort-{name}-{}", std::process::id()));
+
+        let builds = packages
+            .iter()
+            .map(|(pkgname, depends)| {
+                let pkg_dir = dir.join(pkgname);
+
+                fs::create_dir_all(&pkg_dir).unwrap();
+                fs::write(
+                    pkg_dir.join("PKGBUILD"),
+                    format!("pkgname={pkgname}\npkgver=1\npkgrel=1\ndepends=({depends})\n"),
+                )
+                .unwrap();
+
+                Build {
+                    name: pkgname.to_string(),
+                    dir: pkg_dir,
+                    source: "pkgbuild".to_string(),
+                }
+            })
+            .collect();
+
+        (dir, builds)
+    }
+
+    fn names(builds: &[Build]) -> Vec<&str> {
+        builds.iter().map(|build| build.name.as_str()).collect()
+    }
+
+    #[test]
+    fn sort_puts_dependencies_first() {
+        let (dir, chain) = builds(
+            "chain",
+            &[("a", "'b>=1' glibc"), ("b", "c"), ("c", ""), ("d", "c")],
+        );
+
+        assert_eq!(names(&sort(chain, false).unwrap()), ["c", "b", "a", "d"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sort_refuses_cycles() {
+        let (dir, cycle) = builds("cycle", &[("a", "b"), ("b", "c"), ("c", "a")]);
+
+        let error = sort(cycle, false).unwrap_err().to_string();
+
+        assert_eq!(error, "dependency cycle involving a");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,716 @@
+// Keeps clones in the cache up to date: an existing checkout is fetched and moved to the
+// remote HEAD, only a missing or broken one gets cloned from scratch.
+// Urls can pin a ref the way makepkg does, `repo.git#tag=v1.2?signed`.
+// libgit2 can't do partial clones, so blobless ones are handled with the git cli throughout.
+
+use crate::compilers::pkgbuild::signatures::check_status;
+use clap::ValueEnum;
+use git2::{
+    AutotagOption, BranchType, Commit, Direction, FetchOptions, FetchPrune, RemoteCallbacks,
+    Repository, ResetType,
+    build::{CheckoutBuilder, RepoBuilder},
+};
+use std::{
+    fs,
+    io::{Write, stdout},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Strategy {
+    /// Whole history
+    Full,
+    /// Only the last --depth commits
+    Shallow,
+    /// Whole history without file contents, those get fetched when checked out
+    Blobless,
+}
+
+static STRATEGY: OnceLock<Strategy> = OnceLock::new();
+static DEPTH: OnceLock<u32> = OnceLock::new();
+
+// from --clone and --depth, without --clone every caller picks its own default
+pub fn set_strategy(strategy: Option<Strategy>, depth: u32) {
+    if let Some(strategy) = strategy {
+        let _ = STRATEGY.set(strategy);
+    }
+
+    let _ = DEPTH.set(depth.max(1));
+}
+
+fn strategy(url: &str, default: Strategy) -> Strategy {
+    let strategy = STRATEGY.get().copied().unwrap_or(default);
+
+    // libgit2's local transport can't fetch shallow, the history is right there anyway
+    let local = url.starts_with("file://") || !url.contains(':');
+
+    if strategy == Strategy::Shallow && local {
+        Strategy::Full
+    } else {
+        strategy
+    }
+}
+
+fn depth() -> u32 {
+    DEPTH.get().copied().unwrap_or(1)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reference {
+    Head,
+    Branch(String),
+    Tag(String),
+    Commit(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct GitUrl {
+    // what actually gets cloned, without `git+`, the fragment or `?signed`
+    pub url: String,
+    pub reference: Reference,
+    pub signed: bool,
+}
+
+impl GitUrl {
+    pub fn parse(url: &str) -> GitUrl {
+        let url = url.trim_start_matches("git+");
+
+        let (base, fragment) = match url.split_once('#') {
+            Some((base, fragment)) => (base, Some(fragment)),
+            None => (url, None),
+        };
+
+        // `?signed` may come before or after the fragment
+        let (base, base_query) = match base.split_once('?') {
+            Some((base, query)) => (base, Some(query)),
+            None => (base, None),
+        };
+        let (fragment, fragment_query) = match fragment.and_then(|f| f.split_once('?')) {
+            Some((fragment, query)) => (Some(fragment), Some(query)),
+            None => (fragment, None),
+        };
+
+        let reference = match fragment.and_then(|fragment| fragment.split_once('=')) {
+            Some(("branch", name)) => Reference::Branch(name.to_string()),
+            Some(("tag", name)) => Reference::Tag(name.to_string()),
+            Some(("commit", id)) => Reference::Commit(id.to_string()),
+            _ => Reference::Head,
+        };
+
+        GitUrl {
+            url: base.to_string(),
+            reference,
+            signed: base_query == Some("signed") || fragment_query == Some("signed"),
+        }
+    }
+}
+
+// clones `url` into `destination` or updates the clone already there, checks out the
+// pinned ref if there is one and returns the work tree
+pub fn checkout(
+    url: &str,
+    destination: &Path,
+    default: Strategy,
+    gpg_home: Option<&str>,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let git_url = GitUrl::parse(url);
+    let repo_path = clone_or_update(&git_url.url, destination, strategy(&git_url.url, default))?;
+
+    if git_url.reference != Reference::Head {
+        pin(&repo_path, &git_url.reference)?;
+    }
+
+    if git_url.signed {
+        let status = verify(&repo_path, &git_url.reference, gpg_home)?;
+
+        if let Some(problem) = check_status(&status, &[]) {
+            return Err(format!("signature check of {url} failed: {problem}").into());
+        }
+
+        println!("=> \x1b[32;1mSUC:\x1b[0m Signature of {url} is valid");
+    }
+
+    Ok(repo_path)
+}
+
+fn clone_or_update(
+    url: &str,
+    destination: &Path,
+    strategy: Strategy,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    if destination.exists() {
+        // a failed fetch (e.g. no network) leaves the checkout and its sources as they are
+        match unusable(url, destination) {
+            None if is_partial(destination) => return update_partial(url, destination),
+            None => {
+                return update(url, destination, strategy).map_err(|e| {
+                    format!("can't update {}: {}", destination.display(), e.message()).into()
+                });
+            }
+            Some(problem) => {
+                println!(
+                    "=> \x1b[31;1mERR:\x1b[0m {} {problem}\n=> \x1b[33;1mTRY:\x1b[0m Cloning it again..",
+                    destination.to_string_lossy(),
+                );
+
+                fs::remove_dir_all(destination)?;
+            }
+        }
+    }
+
+    println!(
+        "=> \x1b[33;1mTRY:\x1b[0m Cloning {} into {} ({})..",
+        url,
+        destination.to_string_lossy(),
+        describe_strategy(strategy)
+    );
+
+    let repo_path = if strategy == Strategy::Blobless {
+        git(
+            None,
+            &[
+                "clone",
+                "--filter=blob:none",
+                url,
+                &destination.to_string_lossy(),
+            ],
+        )
+        .map_err(|e| format!("failed to clone {url}: {e}"))?;
+
+        destination.to_path_buf()
+    } else {
+        let repo = RepoBuilder::new()
+            .fetch_options(fetch_options(strategy))
+            .clone(url, destination)
+            .map_err(|e| format!("failed to clone {url}: {}", e.message()))?;
+
+        workdir(&repo)
+    };
+
+    println!(
+        "=> \x1b[32;1mSUC:\x1b[0m Successfully cloned: {:?}",
+        repo_path
+    );
+
+    Ok(repo_path)
+}
+
+// fetches origin and fast-forwards, or hard resets if the local history went elsewhere.
+// untracked files (downloaded sources, src/, pkg/) are left alone
+fn update(url: &str, destination: &Path, strategy: Strategy) -> Result<PathBuf, git2::Error> {
+    let repo = Repository::open(destination)?;
+    let mut remote = repo.find_remote("origin")?;
+
+    println!("=> \x1b[33;1mTRY:\x1b[0m Fetching updates for {url}..");
+
+    remote.connect(Direction::Fetch)?;
+    let default_branch = remote.default_branch()?;
+    remote.disconnect()?;
+
+    let branch = default_branch
+        .as_str()
+        .and_then(|name| name.strip_prefix("refs/heads/"))
+        .ok_or_else(|| git2::Error::from_str("remote HEAD is not a branch"))?
+        .to_string();
+
+    let mut options = fetch_options(strategy);
+    options.download_tags(AutotagOption::All);
+
+    remote.fetch(&[] as &[&str], Some(&mut options), None)?;
+
+    let target = repo
+        .find_branch(&format!("origin/{branch}"), BranchType::Remote)?
+        .get()
+        .peel_to_commit()?;
+
+    let head = repo.head().and_then(|head| head.peel_to_commit()).ok();
+
+    match head {
+        Some(head) if head.id() == target.id() => {
+            println!("=> \x1b[1mINFO:\x1b[0m Already up to date");
+        }
+        Some(head) if repo.graph_descendant_of(target.id(), head.id())? => {
+            println!(
+                "=> \x1b[32;1mSUC:\x1b[0m Fast-forwarding {} -> {}",
+                short(&head.id().to_string()),
+                short(&target.id().to_string())
+            );
+        }
+        _ => {
+            println!(
+                "=> \x1b[1mINFO:\x1b[0m Local history differs from origin/{branch}, resetting.."
+            );
+        }
+    }
+
+    // the remote HEAD may have moved to another branch since the clone
+    let local = format!("refs/heads/{branch}");
+
+    if repo
+        .head()
+        .ok()
+        .and_then(|head| head.name().map(String::from))
+        != Some(local.clone())
+    {
+        repo.branch(&branch, &target, true)?;
+        repo.set_head(&local)?;
+    }
+
+    repo.reset(
+        target.as_object(),
+        ResetType::Hard,
+        Some(CheckoutBuilder::new().force()),
+    )?;
+
+    Ok(workdir(&repo))
+}
+
+// why the clone at `destination` has to be thrown away, None if it can be updated
+fn unusable(url: &str, destination: &Path) -> Option<String> {
+    // libgit2 can't open blobless clones, the git cli reads their config
+    let origin = if is_partial(destination) {
+        git_output(destination, &["remote", "get-url", "origin"]).ok()
+    } else {
+        match Repository::open(destination) {
+            Ok(repo) => repo
+                .find_remote("origin")
+                .ok()
+                .and_then(|remote| remote.url().map(String::from)),
+            Err(e) => return Some(format!("is not a usable git repo ({})", e.message())),
+        }
+    };
+
+    match origin {
+        Some(origin) if origin == url => None,
+        Some(origin) => Some(format!("is a clone of {origin}, not {url}")),
+        None => Some("has no origin remote".to_string()),
+    }
+}
+
+// update() for blobless clones
+fn update_partial(url: &str, destination: &Path) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    println!("=> \x1b[33;1mTRY:\x1b[0m Fetching updates for {url}..");
+
+    git(
+        Some(destination),
+        &["remote", "set-head", "origin", "--auto"],
+    )?;
+    git(Some(destination), &["fetch", "--tags", "--force", "origin"])?;
+
+    let remote_head = git_output(
+        destination,
+        &["symbolic-ref", "--short", "refs/remotes/origin/HEAD"],
+    )?;
+    let branch = remote_head.trim_start_matches("origin/");
+
+    git(
+        Some(destination),
+        &["checkout", "--quiet", "--force", "-B", branch, &remote_head],
+    )?;
+
+    Ok(destination.to_path_buf())
+}
+
+// a bare copy with every ref of `url`, what PKGBUILD git sources keep next to the PKGBUILD
+pub fn mirror(
+    url: &str,
+    destination: &Path,
+    default: Strategy,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let strategy = strategy(url, default);
+
+    if is_partial(destination) {
+        println!("=> \x1b[33;1mTRY:\x1b[0m Fetching updates for {url}..");
+
+        return git(Some(destination), &["fetch", "--prune", "origin"]);
+    }
+
+    if let Ok(repo) = Repository::open_bare(destination) {
+        println!("=> \x1b[33;1mTRY:\x1b[0m Fetching updates for {url}..");
+
+        let mut options = fetch_options(strategy);
+        options.prune(FetchPrune::On);
+
+        repo.find_remote("origin")?
+            .fetch(&["+refs/*:refs/*"], Some(&mut options), None)?;
+
+        return Ok(());
+    }
+
+    // a plain clone from before sources were mirrored
+    if destination.exists() {
+        fs::remove_dir_all(destination)?;
+    }
+
+    println!(
+        "=> \x1b[33;1mTRY:\x1b[0m Cloning {url} ({})..",
+        describe_strategy(strategy)
+    );
+
+    if strategy == Strategy::Blobless {
+        git(
+            None,
+            &[
+                "clone",
+                "--mirror",
+                "--filter=blob:none",
+                url,
+                &destination.to_string_lossy(),
+            ],
+        )?;
+
+        // lets clones of the mirror fetch missing blobs through it
+        git(
+            Some(destination),
+            &["config", "uploadpack.allowFilter", "true"],
+        )?;
+        git(
+            Some(destination),
+            &["config", "uploadpack.allowAnySHA1InWant", "true"],
+        )?;
+    } else {
+        RepoBuilder::new()
+            .bare(true)
+            .fetch_options(fetch_options(strategy))
+            .remote_create(|repo, name, url| repo.remote_with_fetch(name, url, "+refs/*:refs/*"))
+            .clone(url, destination)?;
+    }
+
+    Ok(())
+}
+
+// clones a mirror into $srcdir and checks out `reference`
+pub fn clone_mirror(
+    mirror: &Path,
+    target: &Path,
+    reference: &Reference,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if is_partial(mirror) {
+        // the mirror can't pass on blobs it doesn't have, so the clone borrows its
+        // objects and fetches the missing ones from upstream itself
+        let upstream = git_output(mirror, &["remote", "get-url", "origin"])?;
+
+        git(
+            None,
+            &[
+                "clone",
+                "--quiet",
+                "--shared",
+                "--no-checkout",
+                &mirror.to_string_lossy(),
+                &target.to_string_lossy(),
+            ],
+        )?;
+        git(Some(target), &["remote", "add", "upstream", &upstream])?;
+        git(
+            Some(target),
+            &["config", "remote.upstream.promisor", "true"],
+        )?;
+        git(
+            Some(target),
+            &["config", "remote.upstream.partialclonefilter", "blob:none"],
+        )?;
+        git(Some(target), &["reset", "--quiet", "--hard", "HEAD"])?;
+    } else {
+        Repository::clone(&mirror.to_string_lossy(), target)?;
+    }
+
+    if *reference != Reference::Head {
+        pin(target, reference)?;
+    }
+
+    Ok(())
+}
+
+// checks out `reference` with a detached HEAD
+pub fn pin(repo_path: &Path, reference: &Reference) -> Result<(), Box<dyn std::error::Error>> {
+    if is_partial(repo_path) {
+        let id = resolve_cli(repo_path, reference)?;
+
+        println!(
+            "=> \x1b[1mINFO:\x1b[0m Checking out {} ({})",
+            describe(reference),
+            short(&id)
+        );
+
+        return git(
+            Some(repo_path),
+            &["checkout", "--quiet", "--force", "--detach", &id],
+        );
+    }
+
+    let repo = Repository::open(repo_path)?;
+    let commit = resolve(&repo, reference)?;
+
+    println!(
+        "=> \x1b[1mINFO:\x1b[0m Checking out {} ({})",
+        describe(reference),
+        short(&commit.id().to_string())
+    );
+
+    repo.set_head_detached(commit.id())?;
+    repo.checkout_head(Some(CheckoutBuilder::new().force()))?;
+
+    Ok(())
+}
+
+// branches are local in a mirror and under origin/ in a clone of one
+fn resolve<'r>(repo: &'r Repository, reference: &Reference) -> Result<Commit<'r>, git2::Error> {
+    match reference {
+        Reference::Head => repo.head()?.peel_to_commit(),
+        Reference::Branch(name) => repo
+            .find_branch(&format!("origin/{name}"), BranchType::Remote)
+            .or_else(|_| repo.find_branch(name, BranchType::Local))?
+            .get()
+            .peel_to_commit(),
+        Reference::Tag(name) => repo
+            .find_reference(&format!("refs/tags/{name}"))?
+            .peel_to_commit(),
+        Reference::Commit(id) => repo.revparse_single(id)?.peel_to_commit(),
+    }
+}
+
+// resolve() for repos libgit2 can't open
+fn resolve_cli(
+    repo_path: &Path,
+    reference: &Reference,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let candidates = match reference {
+        Reference::Head => vec!["HEAD".to_string()],
+        Reference::Branch(name) => vec![
+            format!("refs/remotes/origin/{name}"),
+            format!("refs/heads/{name}"),
+        ],
+        Reference::Tag(name) => vec![format!("refs/tags/{name}")],
+        Reference::Commit(id) => vec![id.clone()],
+    };
+
+    candidates
+        .iter()
+        .find_map(|candidate| {
+            git_output(
+                repo_path,
+                &[
+                    "rev-parse",
+                    "--verify",
+                    "--quiet",
+                    &format!("{candidate}^{{commit}}"),
+                ],
+            )
+            .ok()
+        })
+        .ok_or_else(|| {
+            format!(
+                "can't find {} in {}",
+                describe(reference),
+                repo_path.to_string_lossy()
+            )
+            .into()
+        })
+}
+
+// gpg's status lines for the signed tag or commit, for signatures::check_status
+pub fn verify(
+    repo_path: &Path,
+    reference: &Reference,
+    gpg_home: Option<&str>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let (command, object) = match reference {
+        Reference::Tag(name) => ("verify-tag", format!("refs/tags/{name}")),
+        _ => ("verify-commit", resolve_cli(repo_path, reference)?),
+    };
+
+    let mut git = Command::new("git");
+
+    if let Some(home) = gpg_home {
+        git.env("GNUPGHOME", home);
+    }
+
+    // --raw puts gpg's status lines on stderr
+    let output = git
+        .arg("-C")
+        .arg(repo_path)
+        .args([command, "--raw", &object])
+        .output()?;
+
+    Ok(String::from_utf8_lossy(&output.stderr).to_string())
+}
+
+pub fn describe(reference: &Reference) -> String {
+    match reference {
+        Reference::Head => "HEAD".to_string(),
+        Reference::Branch(name) => format!("branch {name}"),
+        Reference::Tag(name) => format!("tag {name}"),
+        Reference::Commit(id) => format!("commit {}", short(id)),
+    }
+}
+
+fn describe_strategy(strategy: Strategy) -> String {
+    match strategy {
+        Strategy::Full => "full history".to_string(),
+        Strategy::Shallow => format!("last {} commits", depth()),
+        Strategy::Blobless => "blobless".to_string(),
+    }
+}
+
+// libgit2 can't fetch the blobs a partial clone is missing (older gits mark it with
+// extensions.partialclone, which libgit2 won't even open)
+fn is_partial(repo_path: &Path) -> bool {
+    repo_path.exists()
+        && (git_output(
+            repo_path,
+            &["config", "--get-regexp", r"^remote\..*\.promisor$"],
+        )
+        .is_ok_and(|promisors| promisors.lines().any(|line| line.ends_with(" true")))
+            || git_output(repo_path, &["config", "--get", "extensions.partialclone"]).is_ok())
+}
+
+fn fetch_options(strategy: Strategy) -> FetchOptions<'static> {
+    let mut finished = false;
+    let mut callbacks = RemoteCallbacks::new();
+
+    callbacks.transfer_progress(move |progress| {
+        let (received, total) = (progress.received_objects(), progress.total_objects());
+
+        if total > 0 && !finished {
+            print!(
+                "\r    Received {received}/{total} objects ({} KiB)",
+                progress.received_bytes() / 1024
+            );
+            let _ = stdout().flush();
+
+            if received == total {
+                println!();
+                finished = true;
+            }
+        }
+
+        true
+    });
+
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+
+    if strategy == Strategy::Shallow {
+        options.depth(depth() as i32);
+    }
+
+    options
+}
+
+// runs git with its own progress output
+fn git(dir: Option<&Path>, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut git = Command::new("git");
+
+    if let Some(dir) = dir {
+        git.arg("-C").arg(dir);
+    }
+
+    let status = git.args(args).status()?;
+
+    if !status.success() {
+        return Err(format!("git {} failed", args.join(" ")).into());
+    }
+
+    Ok(())
+}
+
+fn git_output(dir: &Path, args: &[&str]) -> Result<String, Box<dyn std::error::Error>> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!("git {} failed", args.join(" ")).into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn workdir(repo: &Repository) -> PathBuf {
+    repo.workdir().unwrap_or(Path::new("/tmp")).to_path_buf()
+}
+
+fn short(id: &str) -> &str {
+    &id[..7.min(id.len())]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Signature;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uvi-git-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    // a repo with one commit on main, returns its path as the url
+    fn upstream(path: &Path) -> String {
+        let repo = Repository::init(path).unwrap();
+        fs::write(path.join("PKGBUILD"), "pkgname=foo\n").unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("PKGBUILD")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("uvi", "uvi@example.org").unwrap();
+
+        repo.commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn failed_fetch_keeps_the_checkout() {
+        let dir = scratch("offline");
+        let url = upstream(&dir.join("upstream"));
+        let destination = dir.join("clone");
+
+        clone_or_update(&url, &destination, Strategy::Full).unwrap();
+        fs::create_dir(destination.join("src")).unwrap();
+        fs::write(destination.join("foo-1.0.tar.gz"), "downloaded").unwrap();
+
+        // upstream went away, like running offline
+        fs::rename(dir.join("upstream"), dir.join("gone")).unwrap();
+
+        assert!(clone_or_update(&url, &destination, Strategy::Full).is_err());
+        assert!(destination.join("src").is_dir());
+        assert!(destination.join("foo-1.0.tar.gz").exists());
+        assert!(destination.join("PKGBUILD").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn broken_or_foreign_checkouts_are_cloned_again() {
+        let dir = scratch("reclone");
+        let first = upstream(&dir.join("first"));
+        let second = upstream(&dir.join("second"));
+        let destination = dir.join("clone");
+
+        clone_or_update(&first, &destination, Strategy::Full).unwrap();
+        assert_eq!(unusable(&first, &destination), None);
+
+        // origin points somewhere else
+        assert!(unusable(&second, &destination).is_some());
+        clone_or_update(&second, &destination, Strategy::Full).unwrap();
+        assert_eq!(unusable(&second, &destination), None);
+
+        // not a repo anymore
+        fs::remove_dir_all(destination.join(".git")).unwrap();
+        assert!(unusable(&second, &destination).is_some());
+        clone_or_update(&second, &destination, Strategy::Full).unwrap();
+        assert_eq!(unusable(&second, &destination), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,48 +1,129 @@
-// TODO: Check if targetted download is > current, if so; ask user confirmation adn then replace
 // TODO: get pkg-conf working!!
 // TODO: prompkit
 
 use clap::Parser;
-use git2::Repository;
+use database::Package;
+use git2::{DescribeOptions, Repository};
 use reqwest::blocking;
 use rprompt::prompt_reply;
 use std::{
     env,
-    fs::{File, remove_dir_all},
+    fs::{self, File},
     io::copy,
-    panic,
     path::{Path, PathBuf},
     process::{Command, Stdio},
-    thread::sleep,
-    time::Duration,
 };
+use version::Version;
 
 // git_repo requires repo and url to be passed in, change it to just url and seperate using split()
 
+pub mod archive;
 pub mod compilers;
+pub mod conflicts;
+pub mod database;
+pub mod deb;
+pub mod dependencies;
+pub mod git;
+pub mod privilege;
+pub mod rpm;
+pub mod sandbox;
+pub mod search;
+pub mod staging;
+pub mod uninstall;
+pub mod upgrade;
+pub mod version;
 
 #[derive(Parser, Debug)]
 #[command(
     version,
     about = "Global package manager (Used on Uvite. Requires Make, CMake, Meson, and Ninja.)",
-    override_usage = "uvi <NAME> [OPTIONS]"
+    override_usage = "uvi <NAME> [OPTIONS]\n       uvi --upgrade [NAME] [OPTIONS]\n       uvi --search <TERM> [OPTIONS]"
 )]
 struct Args {
     /// Name of package
-    name: String,
+    #[arg(required_unless_present_any = ["upgrade", "search"])]
+    name: Option<String>,
+
+    /// Rebuild installed packages whose source has a newer version, or just NAME
+    #[arg(long, value_name = "NAME", num_args = 0..=1)]
+    upgrade: Option<Option<String>>,
+
+    /// Search the AUR and the Arch packaging GitLab for TERM
+    #[arg(long, value_name = "TERM")]
+    search: Option<String>,
+
+    /// Print --search results as JSON
+    #[arg(long, requires = "search")]
+    json: bool,
+
+    /// Base url of the AUR, used by --search
+    #[arg(long, default_value = "https://aur.archlinux.org/")]
+    aur_url: String,
+
+    /// Base url of the Arch GitLab, used by --search
+    #[arg(long, default_value = "https://gitlab.archlinux.org/")]
+    gitlab_url: String,
 
     /// Uninstall package
     #[arg(long)]
     uninstall: bool,
 
-    /// Build args. Format inside of quotemarks: bargs "arg1 arg2 arg3"
+    /// List what --uninstall would remove without touching anything
     #[arg(long)]
+    dry_run: bool,
+
+    /// Build args. Format inside of quotemarks: bargs "arg1 arg2 arg3"
+    #[arg(long, allow_hyphen_values = true)]
     bargs: Option<String>,
 
     /// Prefix for installing files
     #[arg(long, default_value = "/usr")]
     prefix: String,
 
+    /// Overwrite conflicting files matching this glob, can be given more than once
+    #[arg(long, value_name = "GLOB")]
+    overwrite: Vec<String>,
+
+    /// Don't check for or build missing dependencies
+    #[arg(long)]
+    nodeps: bool,
+
+    /// Don't run the PKGBUILD's check() function
+    #[arg(long)]
+    nocheck: bool,
+
+    /// Don't run the PKGBUILD's prepare() function
+    #[arg(long)]
+    noprepare: bool,
+
+    /// Don't verify PKGBUILD source checksums or signatures (for development only)
+    #[arg(long)]
+    skip_integrity: bool,
+
+    /// Don't verify PGP signatures of PKGBUILD sources
+    #[arg(long)]
+    skip_pgp: bool,
+
+    /// GnuPG home with the keyring used to check source signatures
+    #[arg(long)]
+    gpg_home: Option<String>,
+
+    /// How to clone git repos, by default PKGBUILD repos are shallow and -git sources blobless
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    clone: Option<git::Strategy>,
+
+    /// Commits to fetch with --clone shallow
+    #[arg(long, default_value_t = 1)]
+    depth: u32,
+
+    /// Command used to get root for merging and removing files (sudo, doas, run0..)
+    #[arg(long, default_value = "sudo")]
+    escalate: String,
+
+    /// Build and evaluate PKGBUILDs inside a bubblewrap sandbox: read-only root, hidden home, no network
+    #[arg(long)]
+    sandbox: bool,
+
     /// Like --noconfirm from pacman
     #[arg(long)]
     fast: bool, // essentially --noconfirm
@@ -60,6 +141,38 @@ struct Args {
     repo: String,
 }
 
+// what main() was asked for, handed down so nothing below has to parse argv again
+#[derive(Debug, Clone, Default)]
+pub struct InstallOptions {
+    pub prefix: String,
+    pub bargs: String,
+    pub overwrite: Vec<String>,
+    pub nodeps: bool,
+    pub fast: bool,
+    pub build: bool,
+    pub pkgbuild: compilers::pkgbuild::Options,
+}
+
+impl InstallOptions {
+    fn from_args(args: &Args) -> InstallOptions {
+        InstallOptions {
+            prefix: args.prefix.clone(),
+            bargs: args.bargs.clone().unwrap_or_default(),
+            overwrite: args.overwrite.clone(),
+            nodeps: args.nodeps,
+            fast: args.fast,
+            build: args.build,
+            pkgbuild: compilers::pkgbuild::Options {
+                nocheck: args.nocheck,
+                noprepare: args.noprepare,
+                skip_integrity: args.skip_integrity,
+                skip_pgp: args.skip_pgp,
+                gpg_home: args.gpg_home.clone(),
+            },
+        }
+    }
+}
+
 pub fn fetch_env(target_env: &str) -> PathBuf {
     let home_path = match env::var("HOME") {
         Ok(p) => PathBuf::from(p),
@@ -71,17 +184,32 @@ pub fn fetch_env(target_env: &str) -> PathBuf {
 
     let cache = Path::new(&home_path).join(".cache").join("uvi");
 
-    let current = match target_env {
+    match target_env {
         "HOME" => home_path,
         "CACHE" => cache,
+        "DB" => cache.join("db"),
         _ => cache,
-    };
-
-    current
+    }
 }
 
-fn add_pkg(pkg_name: Vec<(String, String)>) {
-    println!("hi!");
+fn add_pkg(pkg: &Package) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(old) = database::load(&pkg.name) {
+        println!(
+            "=> \x1b[1mINFO:\x1b[0m Replacing {} {} with {}",
+            old.name, old.version, pkg.version
+        );
+    }
+
+    pkg.save()?;
+
+    println!(
+        "=> \x1b[32;1mSUC:\x1b[0m Recorded {} {} ({} files)",
+        pkg.name,
+        pkg.version,
+        pkg.files.len()
+    );
+
+    Ok(())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -93,34 +221,85 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let args = Args::parse();
 
-    let target_destination = Path::new(&args.prefix);
-    let filename = args
-        .name
-        .as_str()
-        .split('/')
-        .last()
-        .unwrap_or("download.tmp");
+    if let Some(term) = &args.search {
+        return search::search(term, &args.aur_url, &args.gitlab_url, args.json);
+    }
 
-    let cache = fetch_env("CACHE");
-    let _home_path = fetch_env("HOME");
+    privilege::set_command(&args.escalate);
+    git::set_strategy(args.clone, args.depth);
 
-    let file_path = cache.join(filename);
+    if args.sandbox {
+        sandbox::enable()?;
+    }
+
+    if privilege::is_root() {
+        println!(
+            "=> \x1b[1mINFO:\x1b[0m Running as root, builds won't be run as an unprivileged user.."
+        );
+    }
 
-    let query = args.name.as_str();
     let repo = match args.repo.as_str() {
         "aur" => "https://aur.archlinux.org/",
         "void" => "https://",
         _ => "https://aur.archlinux.org/",
     };
 
-    if query.ends_with(".git") {
-        git_repo(
+    let options = InstallOptions::from_args(&args);
+
+    if let Some(target) = &args.upgrade {
+        let target = target.as_deref().or(args.name.as_deref());
+
+        return upgrade::upgrade(target, repo, &options);
+    }
+
+    let name = args.name.as_deref().unwrap_or_default();
+
+    let filename = name.split('/').next_back().unwrap_or("download.tmp");
+
+    let cache = fetch_env("CACHE");
+    let _home_path = fetch_env("HOME");
+
+    let file_path = cache.join(filename);
+
+    let query = name;
+
+    // Uninstall handling
+    if args.uninstall {
+        println!("=> Uninstalling: {query}");
+
+        return uninstall::uninstall(query, args.dry_run);
+    }
+
+    // .deb and .rpm packages are installed as they are, there's nothing to build
+    match archive::Format::from_name(filename) {
+        Some(archive::Format::Deb) => {
+            let (path, source) = local_or_fetch(query, &file_path)?;
+            let pkg = deb::stage(&path, args.nodeps, args.fast)?;
+
+            merge_staged(vec![pkg], &source, &options)?;
+            return Ok(());
+        }
+        Some(archive::Format::Rpm) => {
+            let (path, source) = local_or_fetch(query, &file_path)?;
+
+            return rpm::install(&path, &source, &options);
+        }
+        _ => {}
+    }
+
+    let git_url = git::GitUrl::parse(query);
+
+    if git_url.url.ends_with(".git") {
+        // the checkout is named without the #tag=.. fragment
+        let checkout_name = git_url.url.rsplit('/').next().unwrap_or(filename);
+        let (path, source) = git_repo(
             query,
-            Path::new(&file_path),
-            target_destination,
-            &cache,
-            &repo,
+            &cache.join(checkout_name),
+            repo,
+            args.gpg_home.as_deref(),
         )?;
+
+        install_with_dependencies(&path, &source, repo, &options)?;
     }
 
     if args.url {
@@ -130,20 +309,10 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         println!("=> \x1b[1mINFO:\x1b[0m Cache path: {:?}", cache);
 
         if repo == "https://aur.archlinux.org/" {
-            git_repo(
-                &format!("{repo}{query}.git"),
-                Path::new(&file_path),
-                target_destination,
-                &cache,
-                &repo,
-            )?;
-        }
-    }
+            let (path, source) = clone_package(query, repo, args.gpg_home.as_deref())?;
 
-    // Uninstall handling
-    if args.uninstall {
-        println!("=> Uninstalling: {query}");
-        // use pkg-config to find installed package and then uninstall
+            install_with_dependencies(&path, &source, repo, &options)?;
+        }
     }
 
     Ok(())
@@ -151,33 +320,136 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 // DW
 pub fn download(url: &str, destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let response = blocking::get(url)?;
-    let mut dest = File::create(destination)?;
-    let content = response.bytes()?;
+    fetch(url, destination)?;
 
-    copy(&mut content.as_ref(), &mut dest)?;
-    drop(dest);
+    archive::unpack(destination, destination.parent().unwrap())?;
+
+    Ok(())
+}
+
+// download without unpacking, into a .part file first so a failed download never
+// leaves something behind that looks finished
+pub fn fetch(url: &str, destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut partial = destination.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+
+    if let Err(e) = download_to(url, &partial) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
+
+    fs::rename(&partial, destination)?;
 
     println!("=> \x1b[32;1mSUC:\x1b[0m Downloaded file successfully!");
 
-    unpack(destination, destination.parent().unwrap());
+    Ok(())
+}
+
+fn download_to(url: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let mut response = blocking::get(url)?.error_for_status()?;
+    let mut dest = File::create(path)?;
+
+    copy(&mut response, &mut dest)?;
+    dest.sync_all()?;
 
     Ok(())
 }
 
+// a package file given by path is used where it is, a url is downloaded to `destination`
+fn local_or_fetch(
+    query: &str,
+    destination: &Path,
+) -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
+    if Path::new(query).exists() {
+        let path = fs::canonicalize(query)?;
+        let source = path.to_string_lossy().to_string();
+
+        return Ok((path, source));
+    }
+
+    fetch(query, destination)?;
+
+    Ok((destination.to_path_buf(), query.to_string()))
+}
+
+// clones `name` from `repo` into the cache, returns the checkout and the url it came from
+fn clone_package(
+    name: &str,
+    repo: &str,
+    gpg_home: Option<&str>,
+) -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
+    let destination = fetch_env("CACHE").join(name);
+
+    git_repo(&format!("{repo}{name}.git"), &destination, repo, gpg_home)
+}
+
+// builds whatever `destination` needs first, then `destination` itself
+fn install_with_dependencies(
+    destination: &Path,
+    source: &str,
+    repo: &str,
+    options: &InstallOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if options.nodeps {
+        return install(destination, source, options);
+    }
+
+    let name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    println!("=> \x1b[33;1mTRY:\x1b[0m Resolving dependencies..");
+
+    let builds = dependencies::Resolver::new(
+        repo,
+        options.pkgbuild.nocheck,
+        options.pkgbuild.gpg_home.as_deref(),
+    )
+    .resolve(&name, destination, source)?;
+
+    if builds.len() > 1 {
+        let names: Vec<&str> = builds.iter().map(|build| build.name.as_str()).collect();
+
+        println!("=> \x1b[1mINFO:\x1b[0m Build order: {}", names.join(" -> "));
+    }
+
+    for build in builds {
+        install(&build.dir, &build.source, options)?;
+    }
+
+    Ok(())
+}
+
+// PKGBUILD repos only need their last commit, projects built straight from git
+// keep their history so `git describe` gives a version
+fn clone_strategy(url: &str) -> git::Strategy {
+    let recipes = [
+        "https://aur.archlinux.org/",
+        "https://gitlab.archlinux.org/archlinux/packaging/",
+    ];
+
+    if recipes.iter().any(|repo| url.starts_with(repo)) {
+        git::Strategy::Shallow
+    } else {
+        git::Strategy::Full
+    }
+}
+
 fn git_repo(
     url: &str,
     destination: &Path,
-    prefix: &Path,
-    cache: &Path,
     repo: &str,
-) -> Result<(), Box<dyn std::error::Error>> {
+    gpg_home: Option<&str>,
+) -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
     println!("=> \x1b[33;1mTRY:\x1b[0m Testing if repo exists..");
 
     println!("=> \x1b[1mINFO:\x1b[0m Set repo is: {}", &repo);
     println!("=> \x1b[1mINFO:\x1b[0m Set url is: {}", &url);
 
-    let git_pkg_name = &url.rsplit_once("/").unwrap().1;
+    let git_url = git::GitUrl::parse(url);
+    let git_pkg_name = &git_url.url.rsplit_once("/").unwrap().1;
     let pkg_name = git_pkg_name.rsplit_once(".").unwrap().0;
 
     println!("=> \x1b[1mINFO:\x1b[0m Package name: {pkg_name}");
@@ -185,7 +457,7 @@ fn git_repo(
     let formatted_url = format!("{repo}packages/{pkg_name}"); // not finding anything in aur
     let url_status = blocking::get(&formatted_url)?; // same thing as 
 
-    if !url_status.error_for_status().is_ok() {
+    if url_status.error_for_status().is_err() {
         // TODO: Make it so that it doesn't loop when retrying
         println!(
             "=> \x1b[31;1mERR:\x1b[0m Package not found in repo: {}\n=> \x1b[33;1mTRY:\x1b[0m Trying backup repo.. (https://archlinux.org/)",
@@ -197,102 +469,39 @@ fn git_repo(
         git_repo(
             &formatted_url,
             destination,
-            prefix,
-            &cache,
             "https://gitlab.archlinux.org/archlinux/packaging/",
-        )?;
+            gpg_home,
+        )
     } else {
         println!("=> \x1b[32;1mSUC:\x1b[0m Url returned OK!");
 
-        println!(
-            "=> \x1b[33;1mTRY:\x1b[0m Cloning {} into {}..",
-            url,
-            destination.to_string_lossy()
-        );
-
-        if destination.exists() {
-            let delete_prompt = prompt_reply(
-                "=> \x1b[31;1mERR:\x1b[0m Destination already exists..\n=> \x1b[33;1mTRY:\x1b[0m Replace destination? [Y/n]: ",
-            ).expect("Y");
-
-            if delete_prompt != "n" {
-                println!("=> \x1b[33;1mTRY:\x1b[0m Deleting destination..");
+        let repo_path = git::checkout(url, destination, clone_strategy(url), gpg_home)?;
 
-                remove_dir_all(destination).expect("Failed to remove destination..");
-            }
-
-            match Repository::clone(url, destination) {
-                Ok(repo) => {
-                    let mut dir_work = repo.workdir();
-                    let repo_path = dir_work.get_or_insert_with(|| Path::new("/tmp"));
-
-                    println!(
-                        "=> \x1b[32;1mSUC:\x1b[0m Successfully cloned: {:?}",
-                        repo_path
-                    );
-
-                    install(&repo_path)?;
-                }
-                Err(e) => panic!("=> \x1b[31;1mERR:\x1b[0m Failed to clone: {}", e),
-            };
-        } else {
-            match Repository::clone(url, destination) {
-                Ok(repo) => {
-                    let mut dir_work = repo.workdir();
-                    let repo_path = dir_work.get_or_insert_with(|| Path::new("/tmp"));
-
-                    println!(
-                        "=> \x1b[32;1mSUC:\x1b[0m Successfully cloned: {:?}",
-                        repo_path
-                    );
-
-                    install(&repo_path)?;
-                }
-                Err(e) => panic!("=> \x1b[31;1mERR:\x1b[0m Failed to clone: {}", e),
-            };
-        }
-    }
-    Ok(())
-}
-
-pub fn unpack(file_to_unpack: &Path, destination: &Path) {
-    // let file = File::open(file_to_unpack);
-
-    if file_to_unpack
-        .extension()
-        .map_or(false, |ext| ext == "gz" || ext == "xz" || ext == "zst")
-    {
-        println!(
-            "=> \x1b[1mINFO:\x1b[0m Tar detected\n=> \x1b[33;1mTRY:\x1b[0m Unzipping with xzvf..\x1b[0;m"
-        );
-
-        Command::new("tar")
-            .current_dir(destination)
-            .arg("-xzf")
-            .arg(file_to_unpack)
-            // .stdout(Stdio::inherit())
-            // .stderr(Stdio::inherit())
-            .status()
-            .expect("Failed to unzip");
-    } else if file_to_unpack.extension().map_or(false, |ext| ext == "zip") {
-        println!("ugh")
+        Ok((repo_path, url.to_string()))
     }
 }
 
-fn install(destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let _dest_str = destination.to_str();
-    let inst_str = "/usr";
-
-    let args = Args::parse();
-    let build_args = format!("{:?}", args.bargs);
+fn install(
+    destination: &Path,
+    source: &str,
+    options: &InstallOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let inst_str = options.prefix.as_str();
+    let build_args = options.bargs.as_str();
 
     let mut buildable = false;
 
-    if args.build {
+    if options.build {
         buildable = true
     }
 
     let build_dir = destination.join("build");
+    let pkg_name = destination
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let mut installed: Vec<Package> = vec![];
 
     if destination.join("meson.build").exists() {
         if destination.join("Makefile").exists() {
@@ -303,23 +512,67 @@ fn install(destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
         println!("=> Build directory is: {}", build_dir.to_string_lossy());
 
         if buildable {
+            let destdir = staging::prepare(&pkg_name)?;
+
             compilers::meson::build(
                 destination.to_str().unwrap(),
                 &build_dir.to_string_lossy(),
-                &format!("--prefix=/{inst_str} {build_args}"), // not instal_dir/prefix yet
+                &format!("--prefix={inst_str} {build_args}"),
+                &destdir.to_string_lossy(),
             );
+
+            installed.push(Package {
+                name: pkg_name,
+                version: git_version(destination),
+                build_system: "meson".to_string(),
+                ..Default::default()
+            });
         } else {
             println!("Buildable flag disabled..");
         }
+    } else if destination.join("CMakeLists.txt").exists() {
+        println!("=> \x1b[33;1mTRY:\x1b[0m Building with cmake..");
+
+        println!("=> Build directory is: {}", build_dir.to_string_lossy());
+
+        if buildable {
+            let destdir = staging::prepare(&pkg_name)?;
+
+            compilers::cmake::build(
+                destination.to_str().unwrap(),
+                &build_dir.to_string_lossy(),
+                &format!("-DCMAKE_INSTALL_PREFIX={inst_str} {build_args}"),
+                &destdir.to_string_lossy(),
+            );
+
+            installed.push(Package {
+                name: pkg_name,
+                version: git_version(destination),
+                build_system: "cmake".to_string(),
+                ..Default::default()
+            });
+        } else {
+            println!("=> Buildable flag disabled..")
+        }
     } else if destination.join("Makefile").exists() && !destination.join("meson.build").exists() {
         println!("=> \x1b[31mFound a Makefile, building with make..\x1b[0m");
 
         if buildable {
+            let destdir = staging::prepare(&pkg_name)?;
+
             compilers::make::build(
                 destination.to_str().unwrap(),
                 &build_dir.to_string_lossy(),
-                &format!("--prefix=/{inst_str} {build_args}"),
+                &format!("--prefix={inst_str} {build_args}"),
+                &destdir.to_string_lossy(),
             );
+
+            installed.push(Package {
+                name: pkg_name,
+                version: git_version(destination),
+                build_system: "make".to_string(),
+                ..Default::default()
+            });
         } else {
             println!("=> Buildable flag disabled..")
         }
@@ -330,16 +583,130 @@ fn install(destination: &Path) -> Result<(), Box<dyn std::error::Error>> {
             "=> \x1b[1mINFO:\x1b[0m Found PKGBUILD\n=> \x1b[33;1mTRY:\x1b[0m Building with makepkg.."
         );
 
-        compilers::pkgbuild::build(destination);
+        installed.extend(compilers::pkgbuild::build(destination, &options.pkgbuild)?);
     } else {
         println!("=> \x1b[31;1mERR:\x1b[0mno supported build files found, exiting..");
     }
 
-    println!("=>\x1b[32;1m SUC:\x1b[0m Successfully installed to: {inst_str}!");
+    merge_staged(installed, source, options)?;
 
     Ok(())
 }
 
+// a staged package that passed the downgrade and conflict checks, ready to merge
+pub struct Checked {
+    pkg: Package,
+    staged: PathBuf,
+    overwritten: Vec<conflicts::Conflict>,
+}
+
+// records what the package staged and checks it against what's installed, None if the
+// user kept the newer version that's already there
+pub fn check_staged(
+    mut pkg: Package,
+    source: &str,
+    options: &InstallOptions,
+) -> Result<Option<Checked>, Box<dyn std::error::Error>> {
+    let staged = staging::path(&pkg.name);
+
+    pkg.source = source.to_string();
+    // .deb and .rpm packages bring their own, everything else was built for --prefix
+    if pkg.prefix.is_empty() {
+        pkg.prefix = options.prefix.clone();
+        pkg.bargs = options.bargs.clone();
+    }
+    pkg.files = staging::collect_files(&staged);
+
+    if let Some(old) = database::load(&pkg.name)
+        && Version::from(pkg.version.as_str()) < Version::from(old.version.as_str())
+        && !options.fast
+    {
+        let reply = prompt_reply(format!(
+            "=> \x1b[31;1mERR:\x1b[0m {} {} is older than the installed {}..\n=> \x1b[33;1mTRY:\x1b[0m Downgrade anyway? [y/N]: ",
+            pkg.name, pkg.version, old.version
+        ))?;
+
+        if reply != "y" {
+            println!(
+                "=> \x1b[1mINFO:\x1b[0m Keeping {} {}",
+                old.name, old.version
+            );
+            return Ok(None);
+        }
+    }
+
+    let overwritten = conflicts::check(&pkg, &options.overwrite)?;
+
+    Ok(Some(Checked {
+        pkg,
+        staged,
+        overwritten,
+    }))
+}
+
+pub fn merge_checked(checked: Checked) -> Result<(), Box<dyn std::error::Error>> {
+    staging::merge(&checked.staged)?;
+    conflicts::transfer(&checked.pkg.name, &checked.overwritten)?;
+
+    if let Some(old) = database::load(&checked.pkg.name) {
+        uninstall::remove_stale(&old, &checked.pkg)?;
+    }
+
+    add_pkg(&checked.pkg)
+}
+
+// checks, merges and records each package in turn, returns the names of those merged
+fn merge_staged(
+    installed: Vec<Package>,
+    source: &str,
+    options: &InstallOptions,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut merged = vec![];
+    let mut prefix = String::new();
+
+    for pkg in installed {
+        if let Some(checked) = check_staged(pkg, source, options)? {
+            merged.push(checked.pkg.name.clone());
+            prefix = checked.pkg.prefix.clone();
+
+            merge_checked(checked)?;
+        }
+    }
+
+    if !merged.is_empty() {
+        println!("=>\x1b[32;1m SUC:\x1b[0m Successfully installed to: {prefix}!");
+    }
+
+    Ok(merged)
+}
+
+// version of a plain git checkout, e.g. v1.2-3-gdeadbee or just the short hash
+fn git_version(destination: &Path) -> String {
+    let repo = match Repository::open(destination) {
+        Ok(repo) => repo,
+        // blobless clones can only be read with the git cli
+        Err(_) => {
+            return Command::new("git")
+                .arg("-C")
+                .arg(destination)
+                .args(["describe", "--tags", "--always"])
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+                .unwrap_or_else(|| "unknown".to_string());
+        }
+    };
+
+    repo.describe(
+        DescribeOptions::new()
+            .describe_tags()
+            .show_commit_oid_as_fallback(true),
+    )
+    .and_then(|describe| describe.format(None))
+    .unwrap_or_else(|_| "unknown".to_string())
+}
+
 pub fn run_command(dir: &str, name: &str, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
     let status = Command::new(name)
         .current_dir(dir)
@@ -356,6 +723,79 @@ pub fn run_command(dir: &str, name: &str, args: &[&str]) -> Result<(), Box<dyn s
     Ok(())
 }
 
-// // fn deb() {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    #[test]
+    fn fetch_only_leaves_finished_downloads() {
+        let dir = env::temp_dir().join(format!("uvi-main-fetch-{}", std::process::id()));
+        let destination = dir.join("foo-1.0.tar.gz");
+
+        fs::create_dir_all(&dir).unwrap();
+
+        // nothing listens on port 1
+        assert!(fetch("http://127.0.0.1:1/foo-1.0.tar.gz", &destination).is_err());
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/foo-1.0.tar.gz", listener.local_addr().unwrap());
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+
+            let _ = stream.read(&mut request);
+            stream
+                .write_all(
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nhello",
+                )
+                .unwrap();
+        });
 
-// // fn rpm() {}
+        fetch(&url, &destination).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "hello");
+        assert!(!dir.join("foo-1.0.tar.gz.part").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_staged_records_what_it_was_given() {
+        let name = format!("uvi-test-check-{}", std::process::id());
+        let staged = staging::prepare(&name).unwrap();
+
+        fs::create_dir_all(staged.join("opt/uvi-test/bin")).unwrap();
+        fs::write(staged.join("opt/uvi-test/bin/foo"), "").unwrap();
+
+        let options = InstallOptions {
+            prefix: "/opt/uvi-test".to_string(),
+            fast: true,
+            ..Default::default()
+        };
+        let pkg = Package {
+            name: name.clone(),
+            version: "1.0".to_string(),
+            build_system: "make".to_string(),
+            ..Default::default()
+        };
+
+        let checked = check_staged(pkg, "https://example.org/foo.git", &options)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(checked.pkg.prefix, "/opt/uvi-test");
+        assert_eq!(checked.pkg.source, "https://example.org/foo.git");
+        assert_eq!(checked.pkg.files, ["/opt/uvi-test/bin/foo"]);
+        assert!(checked.overwritten.is_empty());
+
+        fs::remove_dir_all(&staged).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+// Builds run as the invoking user, only the steps that touch the live system
+// (merging, removing files) go through the escalation command.
+
+use crate::run_command;
+use std::{fs, sync::OnceLock};
+
+static ESCALATION: OnceLock<String> = OnceLock::new();
+
+// sudo, doas, run0.. whatever --escalate was given
+pub fn set_command(command: &str) {
+    let _ = ESCALATION.set(command.to_string());
+}
+
+pub fn command() -> &'static str {
+    ESCALATION.get().map(|s| s.as_str()).unwrap_or("sudo")
+}
+
+// effective uid from /proc, saves pulling in libc for one call
+pub fn is_root() -> bool {
+    fs::read_to_string("/proc/self/status")
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find(|line| line.starts_with("Uid:"))
+                .and_then(|line| line.split_whitespace().nth(2).map(|euid| euid == "0"))
+        })
+        .unwrap_or(false)
+}
+
+// runs `name args` as root, directly if we already are
+pub fn escalate(dir: &str, name: &str, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    if is_root() {
+        return run_command(dir, name, args);
+    }
+
+    let mut escalated = vec![name];
+    escalated.extend(args);
+
+    run_command(dir, command(), &escalated)
+}
@@ -0,0 +1,503 @@
+// RPM packages: the header is read for the name, version, requirements, file list and
+// scriptlets, and the cpio payload is unpacked into the staging dir and merged like any
+// other package. Scriptlets are shown first and only run as root if the user says so,
+// --fast never agrees to them.
+
+use crate::{
+    InstallOptions,
+    archive::{
+        cpio,
+        rpm::{self, Header},
+    },
+    check_staged,
+    database::Package,
+    dependencies::{self, Dependency},
+    fetch_env, merge_checked, privilege, staging,
+};
+use rprompt::prompt_reply;
+use std::{collections::HashSet, fs, path::Path};
+
+// RPMSENSE_* bits of REQUIREFLAGS
+const LESS: u64 = 0x02;
+const GREATER: u64 = 0x04;
+const EQUAL: u64 = 0x08;
+const RPMLIB: u64 = 1 << 24;
+
+// FILEFLAGS bit for files the package owns but doesn't ship
+const GHOST: u64 = 0x40;
+
+const LIBRARY_DIRS: [&str; 5] = [
+    "/usr/lib",
+    "/usr/lib64",
+    "/lib",
+    "/lib64",
+    "/usr/lib/x86_64-linux-gnu",
+];
+
+pub struct Scriptlet {
+    pub name: &'static str,
+    pub interpreter: Vec<String>,
+    pub body: String,
+}
+
+pub fn install(
+    file: &Path,
+    source: &str,
+    options: &InstallOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (header, payload) = rpm::open(file)?;
+
+    let name = header
+        .string(rpm::NAME)
+        .ok_or_else(|| format!("{} has no package name", file.display()))?;
+    let version = version(&header);
+
+    println!("=> \x1b[1mINFO:\x1b[0m Found {name} {version} (.rpm)");
+
+    if !options.nodeps {
+        check_requires(&header, &name, options.fast)?;
+    }
+
+    let (pre, post) = (
+        scriptlet(&header, "%pre", rpm::PREIN, rpm::PREINPROG),
+        scriptlet(&header, "%post", rpm::POSTIN, rpm::POSTINPROG),
+    );
+
+    let scriptlets: Vec<&Scriptlet> = pre.iter().chain(post.iter()).collect();
+    let run_scriptlets = !scriptlets.is_empty() && confirm_scriptlets(&scriptlets, options.fast)?;
+
+    if [rpm::PREUN, rpm::POSTUN]
+        .iter()
+        .any(|tag| header.string(*tag).is_some())
+    {
+        println!(
+            "=> \x1b[1mINFO:\x1b[0m {name} has %preun/%postun scriptlets, --uninstall won't run them"
+        );
+    }
+
+    let staged = staging::prepare(&name)?;
+
+    println!("=> \x1b[33;1mTRY:\x1b[0m Unpacking the payload..");
+
+    cpio::extract(payload, &staged)?;
+    check_payload(&header, &staged)?;
+
+    let checked = check_staged(
+        Package {
+            name: name.clone(),
+            version,
+            build_system: "rpm".to_string(),
+            // every path in an .rpm is absolute, --prefix doesn't apply
+            prefix: "/".to_string(),
+            ..Default::default()
+        },
+        source,
+        options,
+    )?;
+
+    // a package that was kept back or conflicts never gets its scriptlets run
+    let checked = match checked {
+        Some(checked) => checked,
+        None => return Ok(()),
+    };
+
+    if run_scriptlets && let Some(pre) = &pre {
+        run(pre, &name)?;
+    }
+
+    merge_checked(checked)?;
+
+    if run_scriptlets && let Some(post) = &post {
+        run(post, &name)?;
+    }
+
+    println!("=>\x1b[32;1m SUC:\x1b[0m Successfully installed to: /!");
+
+    Ok(())
+}
+
+pub fn version(header: &Header) -> String {
+    let version = header.string(rpm::VERSION).unwrap_or_default();
+    let release = header.string(rpm::RELEASE).unwrap_or_default();
+
+    match header.numbers(rpm::EPOCH).first() {
+        Some(epoch) => format!("{epoch}:{version}-{release}"),
+        None => format!("{version}-{release}"),
+    }
+}
+
+// every path the header lists, in the order of the file tags
+fn files(header: &Header) -> Vec<String> {
+    let basenames = header.strings(rpm::BASENAMES);
+
+    // before rpm 4 the whole path was stored in one tag
+    if basenames.is_empty() {
+        return header.strings(rpm::OLDFILENAMES);
+    }
+
+    let dirnames = header.strings(rpm::DIRNAMES);
+    let indexes = header.numbers(rpm::DIRINDEXES);
+
+    basenames
+        .iter()
+        .zip(indexes)
+        .map(|(basename, index)| {
+            let dirname = dirnames
+                .get(index as usize)
+                .map(|dirname| dirname.as_str())
+                .unwrap_or("/");
+
+            format!("{dirname}{basename}")
+        })
+        .collect()
+}
+
+// everything the header lists has to be in the payload, except %ghost files
+fn check_payload(header: &Header, staged: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let flags = header.numbers(rpm::FILEFLAGS);
+
+    for (index, file) in files(header).iter().enumerate() {
+        if flags.get(index).is_some_and(|flag| flag & GHOST != 0) {
+            continue;
+        }
+
+        let relative = file.trim_start_matches('/');
+
+        if staged.join(relative).symlink_metadata().is_err() {
+            return Err(format!("{file} is listed in the header but not in the payload").into());
+        }
+    }
+
+    Ok(())
+}
+
+fn check_requires(
+    header: &Header,
+    name: &str,
+    fast: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    println!("=> \x1b[33;1mTRY:\x1b[0m Checking dependencies..");
+
+    let names = header.strings(rpm::REQUIRENAME);
+    let flags = header.numbers(rpm::REQUIREFLAGS);
+    let versions = header.strings(rpm::REQUIREVERSION);
+
+    // a package can require what it provides itself, e.g. its own sonames
+    let provides: HashSet<String> = header.strings(rpm::PROVIDENAME).into_iter().collect();
+    let own_files: HashSet<String> = files(header).into_iter().collect();
+
+    let mut missing: Vec<String> = vec![];
+    let mut unchecked: Vec<String> = vec![];
+
+    for (index, required) in names.iter().enumerate() {
+        let flag = flags.get(index).copied().unwrap_or(0);
+        let version = versions.get(index).map(|v| v.as_str()).unwrap_or("");
+
+        let operator = operator(flag, version);
+
+        // rpmlib(...) are features of rpm itself
+        if flag & RPMLIB != 0
+            || required.starts_with("rpmlib(")
+            || required.starts_with("config(")
+            || provides.contains(required)
+            || own_files.contains(required)
+        {
+            continue;
+        }
+
+        let satisfied = if required.starts_with('/') {
+            Path::new(required).exists()
+        } else if let Some((library, _)) = required.split_once(".so")
+            && !library.contains('(')
+        {
+            // libc.so.6()(64bit) or libc.so.6(GLIBC_2.34)(64bit)
+            let soname = required.split('(').next().unwrap_or(required);
+
+            LIBRARY_DIRS
+                .iter()
+                .any(|dir| Path::new(dir).join(soname).exists())
+        } else if required.contains('(') {
+            // perl(Foo::Bar), python3dist(foo).. nothing outside rpm knows these
+            if !unchecked.contains(required) {
+                unchecked.push(required.clone());
+            }
+            continue;
+        } else {
+            let dependency = if operator.is_empty() {
+                Dependency::parse(required)
+            } else {
+                Dependency::parse(&format!("{required}{operator}{version}"))
+            };
+
+            dependencies::satisfied(&dependency)
+        };
+
+        let display = if operator.is_empty() {
+            required.clone()
+        } else {
+            format!("{required} {operator} {version}")
+        };
+
+        if !satisfied && !missing.contains(&display) {
+            missing.push(display);
+        }
+    }
+
+    if !unchecked.is_empty() {
+        println!(
+            "=> \x1b[1mINFO:\x1b[0m Can't check {}, make sure they're installed",
+            unchecked.join(", ")
+        );
+    }
+
+    dependencies::confirm_missing(name, &missing, fast)
+}
+
+// the RPMSENSE_* comparison bits of a require, as the operator uvi's dependencies use
+fn operator(flag: u64, version: &str) -> &'static str {
+    match flag & (LESS | GREATER | EQUAL) {
+        _ if version.is_empty() => "",
+        LESS => "<",
+        GREATER => ">",
+        EQUAL => "=",
+        flag if flag == LESS | EQUAL => "<=",
+        flag if flag == GREATER | EQUAL => ">=",
+        _ => "",
+    }
+}
+
+fn scriptlet(
+    header: &Header,
+    name: &'static str,
+    body: u32,
+    interpreter: u32,
+) -> Option<Scriptlet> {
+    let body = header.string(body)?;
+    let mut interpreter = header.strings(interpreter);
+
+    // no interpreter means the default shell
+    if interpreter.is_empty() {
+        interpreter.push("/bin/sh".to_string());
+    }
+
+    Some(Scriptlet {
+        name,
+        interpreter,
+        body,
+    })
+}
+
+fn confirm_scriptlets(
+    scriptlets: &[&Scriptlet],
+    fast: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    for scriptlet in scriptlets {
+        println!(
+            "\n    \x1b[1m{} ({})\x1b[0m",
+            scriptlet.name,
+            scriptlet.interpreter.join(" ")
+        );
+
+        for line in scriptlet.body.lines() {
+            println!("    {line}");
+        }
+    }
+
+    println!();
+
+    if fast {
+        println!(
+            "=> \x1b[1mINFO:\x1b[0m Not running scriptlets with --fast, run them by hand if needed"
+        );
+        return Ok(false);
+    }
+
+    let reply = prompt_reply("=> \x1b[33;1mTRY:\x1b[0m Run these scriptlets as root? [y/N]: ")?;
+
+    Ok(reply == "y")
+}
+
+// rpm passes the number of installed instances, 1 on a fresh install
+fn run(scriptlet: &Scriptlet, package: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if scriptlet.interpreter[0] == "<lua>" {
+        println!(
+            "=> \x1b[31;1mERR:\x1b[0m {} is written in rpm's embedded lua, uvi can't run it",
+            scriptlet.name
+        );
+        return Ok(());
+    }
+
+    let script = fetch_env("CACHE").join("scriptlets").join(format!(
+        "{package}-{}",
+        scriptlet.name.trim_start_matches('%')
+    ));
+
+    fs::create_dir_all(script.parent().unwrap())?;
+    fs::write(&script, &scriptlet.body)?;
+
+    println!("=> \x1b[33;1mTRY:\x1b[0m Running {}..", scriptlet.name);
+
+    let script = script.to_string_lossy();
+    let mut args: Vec<&str> = scriptlet.interpreter[1..]
+        .iter()
+        .map(|arg| arg.as_str())
+        .collect();
+    args.extend([script.as_ref(), "1"]);
+
+    privilege::escalate("/", &scriptlet.interpreter[0], &args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::rpm::tests::{Value, header, package, scratch};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn parse(entries: &[(u32, Value)]) -> Header {
+        // tests run in parallel, each parse gets its own dir
+        static PARSED: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = scratch(&format!(
+            "header-{}",
+            PARSED.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = dir.join("test.rpm");
+
+        fs::write(&file, package(&header(entries), b"")).unwrap();
+
+        let header = rpm::open(&file).unwrap().0;
+        fs::remove_dir_all(&dir).unwrap();
+
+        header
+    }
+
+    #[test]
+    fn name_and_version() {
+        let header = parse(&[
+            (rpm::NAME, Value::String("hello")),
+            (rpm::VERSION, Value::String("2.12.1")),
+            (rpm::RELEASE, Value::String("3.fc41")),
+            (rpm::EPOCH, Value::Int32(&[2])),
+        ]);
+
+        assert_eq!(header.string(rpm::NAME).as_deref(), Some("hello"));
+        assert_eq!(version(&header), "2:2.12.1-3.fc41");
+
+        let header = parse(&[
+            (rpm::VERSION, Value::String("2.12.1")),
+            (rpm::RELEASE, Value::String("3.fc41")),
+        ]);
+
+        assert_eq!(version(&header), "2.12.1-3.fc41");
+    }
+
+    #[test]
+    fn require_operators() {
+        let header = parse(&[
+            (
+                rpm::REQUIRENAME,
+                Value::Strings(&[
+                    "glibc",
+                    "foo",
+                    "bar",
+                    "baz",
+                    "qux",
+                    "rpmlib(PayloadIsZstd)",
+                    "sh",
+                ]),
+            ),
+            (
+                rpm::REQUIREFLAGS,
+                Value::Int32(&[
+                    (GREATER | EQUAL) as u32,
+                    LESS as u32,
+                    EQUAL as u32,
+                    (LESS | EQUAL) as u32,
+                    GREATER as u32,
+                    (RPMLIB | LESS | EQUAL) as u32,
+                    // RPMSENSE_INTERP, no comparison
+                    0x100,
+                ]),
+            ),
+            (
+                rpm::REQUIREVERSION,
+                Value::Strings(&["2.34", "3", "1:2.0-1", "4.1", "0.9", "5.4.18-1", ""]),
+            ),
+        ]);
+
+        let names = header.strings(rpm::REQUIRENAME);
+        let flags = header.numbers(rpm::REQUIREFLAGS);
+        let versions = header.strings(rpm::REQUIREVERSION);
+
+        let requires: Vec<String> = names
+            .iter()
+            .zip(flags)
+            .zip(&versions)
+            .map(|((name, flag), version)| format!("{name}{}{version}", operator(flag, version)))
+            .collect();
+
+        assert_eq!(
+            requires,
+            [
+                "glibc>=2.34",
+                "foo<3",
+                "bar=1:2.0-1",
+                "baz<=4.1",
+                "qux>0.9",
+                "rpmlib(PayloadIsZstd)<=5.4.18-1",
+                "sh",
+            ]
+        );
+
+        // no version means no comparison, whatever the flags say
+        assert_eq!(operator(GREATER | EQUAL, ""), "");
+    }
+
+    #[test]
+    fn files_and_ghosts() {
+        let header = parse(&[
+            (
+                rpm::BASENAMES,
+                Value::Strings(&["hello", "hello.1.gz", "hello.log"]),
+            ),
+            (
+                rpm::DIRNAMES,
+                Value::Strings(&["/usr/bin/", "/usr/share/man/man1/", "/var/log/"]),
+            ),
+            (rpm::DIRINDEXES, Value::Int32(&[0, 1, 2])),
+            (rpm::FILEFLAGS, Value::Int32(&[0, 0x2, GHOST as u32])),
+        ]);
+
+        assert_eq!(
+            files(&header),
+            [
+                "/usr/bin/hello",
+                "/usr/share/man/man1/hello.1.gz",
+                "/var/log/hello.log"
+            ]
+        );
+
+        let staged = scratch("ghost");
+        fs::create_dir_all(staged.join("usr/bin")).unwrap();
+        fs::write(staged.join("usr/bin/hello"), "").unwrap();
+
+        // the man page is missing from the payload
+        assert_eq!(
+            check_payload(&header, &staged).unwrap_err().to_string(),
+            "/usr/share/man/man1/hello.1.gz is listed in the header but not in the payload"
+        );
+
+        // the %ghost log never is, and that's fine
+        fs::create_dir_all(staged.join("usr/share/man/man1")).unwrap();
+        fs::write(staged.join("usr/share/man/man1/hello.1.gz"), "").unwrap();
+
+        assert!(check_payload(&header, &staged).is_ok());
+
+        fs::remove_dir_all(&staged).unwrap();
+
+        // rpm 3 kept whole paths in one tag
+        let header = parse(&[(rpm::OLDFILENAMES, Value::Strings(&["/usr/bin/hello"]))]);
+
+        assert_eq!(files(&header), ["/usr/bin/hello"]);
+    }
+}
@@ -0,0 +1,139 @@
+// Optional bubblewrap jail for the build backends and PKGBUILD evaluation: read-only root,
+// an empty $HOME, no network, the working dir readable and only the dirs passed in writable.
+
+use crate::{fetch_env, run_command};
+use std::{
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+// set from --sandbox, fails early if bwrap isn't installed
+pub fn enable() -> Result<(), Box<dyn std::error::Error>> {
+    let found = Command::new("bwrap")
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+
+    if !found {
+        return Err("--sandbox needs bubblewrap (bwrap) installed".into());
+    }
+
+    let _ = ENABLED.set(true);
+
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    ENABLED.get().copied().unwrap_or(false)
+}
+
+// runs `name args` in `dir`, jailed when --sandbox was given
+pub fn run(
+    dir: &str,
+    writable: &[&str],
+    name: &str,
+    args: &[&str],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (program, wrapped) = wrap(dir, writable, name, args);
+    let wrapped: Vec<&str> = wrapped.iter().map(|s| s.as_str()).collect();
+
+    run_command(dir, &program, &wrapped)
+}
+
+// same as run() but hands back the Command, for callers that want the output
+pub fn command(dir: &str, writable: &[&str], name: &str, args: &[&str]) -> Command {
+    let (program, wrapped) = wrap(dir, writable, name, args);
+
+    let mut command = Command::new(program);
+    command.current_dir(dir).args(wrapped);
+
+    command
+}
+
+fn wrap(dir: &str, writable: &[&str], name: &str, args: &[&str]) -> (String, Vec<String>) {
+    if !enabled() {
+        return (
+            name.to_string(),
+            args.iter().map(|arg| arg.to_string()).collect(),
+        );
+    }
+
+    let home = fetch_env("HOME").to_string_lossy().to_string();
+
+    (
+        "bwrap".to_string(),
+        bwrap_args(&home, dir, writable, name, args),
+    )
+}
+
+fn bwrap_args(home: &str, dir: &str, writable: &[&str], name: &str, args: &[&str]) -> Vec<String> {
+    let mut wrapped: Vec<String> = [
+        "--unshare-user",
+        "--unshare-ipc",
+        "--unshare-pid",
+        "--unshare-uts",
+        "--unshare-cgroup-try",
+        "--unshare-net",
+        "--die-with-parent",
+        "--new-session",
+        "--ro-bind",
+        "/",
+        "/",
+        "--dev",
+        "/dev",
+        "--proc",
+        "/proc",
+        "--tmpfs",
+        "/tmp",
+        "--tmpfs",
+        home,
+    ]
+    .iter()
+    .map(|arg| arg.to_string())
+    .collect();
+
+    // binds come after the $HOME tmpfs, the build dirs live under ~/.cache. The working
+    // dir is only read from unless it's also writable, the later bind wins
+    wrapped.extend(["--ro-bind".to_string(), dir.to_string(), dir.to_string()]);
+
+    for path in writable {
+        wrapped.extend(["--bind".to_string(), path.to_string(), path.to_string()]);
+    }
+
+    wrapped.extend(["--chdir".to_string(), dir.to_string(), "--".to_string()]);
+    wrapped.push(name.to_string());
+    wrapped.extend(args.iter().map(|arg| arg.to_string()));
+
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn working_dir_is_read_only_unless_writable() {
+        let home = "/home/user";
+        let dir = "/home/user/.cache/uvi/foo";
+
+        let args = bwrap_args(home, dir, &[], "bash", &["-c", "true"]);
+        let position = |arg: &[&str]| args.windows(arg.len()).position(|window| window == arg);
+
+        let tmpfs = position(&["--tmpfs", home]).unwrap();
+        let read_only = position(&["--ro-bind", dir, dir]).unwrap();
+
+        assert!(tmpfs < read_only);
+        assert!(position(&["--bind", dir, dir]).is_none());
+        assert!(args.contains(&"--unshare-net".to_string()));
+        assert_eq!(&args[args.len() - 4..], ["--", "bash", "-c", "true"]);
+
+        let args = bwrap_args(home, dir, &[dir], "make", &[]);
+        let position = |arg: &[&str]| args.windows(arg.len()).position(|window| window == arg);
+
+        assert!(position(&["--ro-bind", dir, dir]) < position(&["--bind", dir, dir]));
+    }
+}
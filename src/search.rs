@@ -0,0 +1,386 @@
+// --search: asks the AUR RPC (v5) and the Arch packaging GitLab for packages matching a term.
+// Both base urls come from the command line so this can be pointed at a local stand-in.
+
+use reqwest::{Url, blocking};
+use serde_json::{Value, json};
+
+// how many GitLab projects get their latest tag looked up, one request each
+const GITLAB_LIMIT: usize = 20;
+
+struct Found {
+    repo: &'static str,
+    name: String,
+    // GitLab projects without a release tag have none
+    version: Option<String>,
+    votes: Option<u64>,
+    description: String,
+    // unix time the package was flagged out of date, AUR only
+    out_of_date: Option<u64>,
+}
+
+pub fn search(
+    term: &str,
+    aur_url: &str,
+    gitlab_url: &str,
+    as_json: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let found = find(term, aur_url, gitlab_url)?;
+
+    print!("{}", render(&found, term, as_json)?);
+
+    Ok(())
+}
+
+// everything both repos have for `term`, exact matches first
+fn find(
+    term: &str,
+    aur_url: &str,
+    gitlab_url: &str,
+) -> Result<Vec<Found>, Box<dyn std::error::Error>> {
+    let mut found: Vec<Found> = vec![];
+    let mut failures = 0;
+
+    for (repo, result) in [
+        ("aur", aur(term, aur_url)),
+        ("gitlab", gitlab(term, gitlab_url)),
+    ] {
+        match result {
+            Ok(packages) => found.extend(packages),
+            Err(e) => {
+                failures += 1;
+                // stderr so --json output stays parseable
+                eprintln!("=> \x1b[31;1mERR:\x1b[0m Searching {repo} failed: {e}");
+            }
+        }
+    }
+
+    if failures == 2 {
+        return Err("no repo could be searched".into());
+    }
+
+    // exact matches first, then by name
+    found.sort_by(|a, b| {
+        (a.name != term)
+            .cmp(&(b.name != term))
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.repo.cmp(b.repo))
+    });
+
+    Ok(found)
+}
+
+fn render(
+    found: &[Found],
+    term: &str,
+    as_json: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if as_json {
+        let entries: Vec<Value> = found
+            .iter()
+            .map(|pkg| {
+                json!({
+                    "repo": pkg.repo,
+                    "name": pkg.name,
+                    "version": pkg.version,
+                    "votes": pkg.votes,
+                    "description": pkg.description,
+                    "out_of_date": pkg.out_of_date,
+                })
+            })
+            .collect();
+
+        Ok(format!("{}\n", serde_json::to_string_pretty(&entries)?))
+    } else if found.is_empty() {
+        Ok(format!(
+            "=> \x1b[1mINFO:\x1b[0m Nothing found for {term}..\n"
+        ))
+    } else {
+        Ok(format_results(found))
+    }
+}
+
+fn aur(term: &str, base: &str) -> Result<Vec<Found>, Box<dyn std::error::Error>> {
+    let rpc = format!("{}/rpc/", base.trim_end_matches('/'));
+
+    let mut reply = get_json(Url::parse_with_params(
+        &rpc,
+        &[
+            ("v", "5"),
+            ("type", "search"),
+            ("by", "name-desc"),
+            ("arg", term),
+        ],
+    )?)?;
+
+    // terms that are too short or match too much are refused by search, an exact name
+    // can still be looked up through info
+    if reply["type"] == "error" {
+        let error = reply["error"]
+            .as_str()
+            .unwrap_or("unknown error")
+            .to_string();
+
+        reply = get_json(Url::parse_with_params(
+            &rpc,
+            &[("v", "5"), ("type", "info"), ("arg[]", term)],
+        )?)?;
+
+        if reply["type"] == "error" || reply["resultcount"] == 0 {
+            return Err(error.into());
+        }
+    }
+
+    let results = reply["results"].as_array().cloned().unwrap_or_default();
+
+    Ok(results
+        .iter()
+        .map(|result| Found {
+            repo: "aur",
+            name: text(&result["Name"]),
+            version: result["Version"].as_str().map(String::from),
+            votes: result["NumVotes"].as_u64(),
+            description: text(&result["Description"]),
+            out_of_date: result["OutOfDate"].as_u64(),
+        })
+        .collect())
+}
+
+fn gitlab(term: &str, base: &str) -> Result<Vec<Found>, Box<dyn std::error::Error>> {
+    let api = format!("{}/api/v4", base.trim_end_matches('/'));
+
+    let projects = get_json(Url::parse_with_params(
+        &format!("{api}/groups/archlinux%2Fpackaging%2Fpackages/projects"),
+        &[
+            ("search", term),
+            ("simple", "true"),
+            ("per_page", &GITLAB_LIMIT.to_string()),
+        ],
+    )?)?;
+
+    let mut found: Vec<Found> = vec![];
+
+    for project in projects.as_array().cloned().unwrap_or_default() {
+        // the packaging repos don't keep a version anywhere but their release tags
+        let tags = get_json(Url::parse_with_params(
+            &format!("{api}/projects/{}/repository/tags", project["id"]),
+            &[("per_page", "1")],
+        )?)?;
+
+        found.push(Found {
+            repo: "gitlab",
+            name: text(&project["name"]),
+            version: tags[0]["name"].as_str().map(tag_version),
+            votes: None,
+            description: text(&project["description"]),
+            out_of_date: None,
+        });
+    }
+
+    Ok(found)
+}
+
+fn get_json(url: Url) -> Result<Value, Box<dyn std::error::Error>> {
+    let body = blocking::get(url)?.error_for_status()?.text()?;
+
+    Ok(serde_json::from_str(&body)?)
+}
+
+fn text(value: &Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+// tags can't hold a `:`, so 1:2.0-3 is tagged as 1-2.0-3
+fn tag_version(tag: &str) -> String {
+    match tag.split('-').collect::<Vec<&str>>()[..] {
+        [epoch, pkgver, pkgrel] if epoch.chars().all(|c| c.is_ascii_digit()) => {
+            format!("{epoch}:{pkgver}-{pkgrel}")
+        }
+        _ => tag.to_string(),
+    }
+}
+
+fn format_results(found: &[Found]) -> String {
+    let mut out = String::from("\n");
+
+    for pkg in found {
+        let votes = match pkg.votes {
+            Some(votes) => format!(" ({votes} votes)"),
+            None => String::new(),
+        };
+
+        let flagged = match pkg.out_of_date {
+            Some(_) => " \x1b[31;1m(out of date)\x1b[0m",
+            None => "",
+        };
+
+        out.push_str(&format!(
+            "\x1b[35;1m{}/\x1b[0m\x1b[1m{}\x1b[0m \x1b[32;1m{}\x1b[0m{votes}{flagged}\n",
+            pkg.repo,
+            pkg.name,
+            pkg.version.as_deref().unwrap_or("?")
+        ));
+
+        if !pkg.description.is_empty() {
+            out.push_str(&format!("    {}\n", pkg.description));
+        }
+    }
+
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    // answers every request with the body of the first route its request line contains,
+    // 404 otherwise. returns the base url
+    fn serve(routes: Vec<(&'static str, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+
+                reader.read_line(&mut request_line).unwrap();
+
+                // the rest of the headers, up to the blank line
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+
+                let (status, body) = routes
+                    .iter()
+                    .find(|(route, _)| request_line.contains(route))
+                    .map(|(_, body)| ("200 OK", *body))
+                    .unwrap_or(("404 Not Found", "{}"));
+
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        base
+    }
+
+    const AUR_SEARCH: &str = r#"{"version":5,"type":"search","resultcount":2,"results":[
+        {"Name":"foo-git","Version":"r12.abc-1","NumVotes":3,"Description":"Foo, from git","OutOfDate":null},
+        {"Name":"foo","Version":"1:2.0-3","NumVotes":12,"Description":"The foo tool","OutOfDate":1700000000}
+    ]}"#;
+
+    const GITLAB_PROJECTS: &str = r#"[
+        {"id":7,"name":"foo","description":"The foo tool"},
+        {"id":8,"name":"foo-extras","description":""}
+    ]"#;
+
+    #[test]
+    fn results_from_both_repos() {
+        let aur = serve(vec![("type=search", AUR_SEARCH)]);
+        let gitlab = serve(vec![
+            ("/projects?search=foo", GITLAB_PROJECTS),
+            ("/projects/7/repository/tags", r#"[{"name":"1-2.0-3"}]"#),
+            ("/projects/8/repository/tags", "[]"),
+        ]);
+
+        let found = find("foo", &aur, &gitlab).unwrap();
+        let names: Vec<String> = found
+            .iter()
+            .map(|pkg| format!("{}/{}", pkg.repo, pkg.name))
+            .collect();
+
+        // exact matches first
+        assert_eq!(
+            names,
+            ["aur/foo", "gitlab/foo", "gitlab/foo-extras", "aur/foo-git"]
+        );
+
+        let text = render(&found, "foo", false).unwrap();
+
+        assert!(text.contains(
+            "\x1b[35;1maur/\x1b[0m\x1b[1mfoo\x1b[0m \x1b[32;1m1:2.0-3\x1b[0m (12 votes) \x1b[31;1m(out of date)\x1b[0m\n    The foo tool\n"
+        ));
+        // the tag 1-2.0-3 is the version 1:2.0-3, no tag at all is unknown
+        assert!(text.contains("\x1b[1mfoo\x1b[0m \x1b[32;1m1:2.0-3\x1b[0m\n    The foo tool\n"));
+        assert!(text.contains("\x1b[1mfoo-extras\x1b[0m \x1b[32;1m?\x1b[0m\n"));
+
+        let json: Value = serde_json::from_str(&render(&found, "foo", true).unwrap()).unwrap();
+
+        assert_eq!(
+            json[0],
+            json!({
+                "repo": "aur",
+                "name": "foo",
+                "version": "1:2.0-3",
+                "votes": 12,
+                "description": "The foo tool",
+                "out_of_date": 1700000000,
+            })
+        );
+        assert_eq!(json[2]["version"], Value::Null);
+        assert_eq!(json.as_array().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn refused_search_falls_back_to_info() {
+        let aur = serve(vec![
+            (
+                "type=search",
+                r#"{"version":5,"type":"error","resultcount":0,"results":[],"error":"Query arg too small."}"#,
+            ),
+            (
+                "type=info&arg%5B%5D=qt",
+                r#"{"version":5,"type":"multiinfo","resultcount":1,"results":[
+                    {"Name":"qt","Version":"1.0-1","NumVotes":1,"Description":"","OutOfDate":null}
+                ]}"#,
+            ),
+            (
+                "type=info",
+                r#"{"version":5,"type":"multiinfo","resultcount":0,"results":[]}"#,
+            ),
+        ]);
+        let gitlab = serve(vec![("/projects?search=", "[]")]);
+
+        let found = find("qt", &aur, &gitlab).unwrap();
+
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name, "qt");
+
+        // nothing through info either, the search error is what gets reported
+        assert!(aur_error("xy", &aur).contains("Query arg too small."));
+
+        // gitlab alone still answered, so it's an empty result rather than a failure
+        let nothing = find("xy", &aur, &gitlab).unwrap();
+
+        assert_eq!(
+            render(&nothing, "xy", false).unwrap(),
+            "=> \x1b[1mINFO:\x1b[0m Nothing found for xy..\n"
+        );
+        assert_eq!(render(&nothing, "xy", true).unwrap(), "[]\n");
+    }
+
+    #[test]
+    fn both_repos_failing_is_an_error() {
+        let down = serve(vec![]);
+
+        assert!(find("foo", &down, &down).is_err());
+    }
+
+    fn aur_error(term: &str, base: &str) -> String {
+        match aur(term, base) {
+            Ok(_) => panic!("{term} was found"),
+            Err(e) => e.to_string(),
+        }
+    }
+}
@@ -0,0 +1,263 @@
+// Every backend installs into a per-package staging dir (DESTDIR / $pkgdir) first,
+// the staged tree is then merged into / in one go. Files are copied as root, so they
+// land owned by root unless the owners list next to the staging dir says otherwise.
+
+use crate::{fetch_env, privilege};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+// copies every file next to its target and renames it over, so a file is never half written.
+// directories uvi creates get the mode they were staged with, existing ones are left alone
+const MERGE_SCRIPT: &str = r#"
+cd "$1" || exit 1
+find . -mindepth 1 -type d -print0 | while IFS= read -r -d '' d; do
+    [ -d "$2/$d" ] && continue
+    mkdir -p "$2/$d" && chmod "$(stat -c %a "$d")" "$2/$d" || exit 1
+done || exit 1
+find . ! -type d -print0 | while IFS= read -r -d '' f; do
+    cp -d --preserve=mode,timestamps "$f" "$2/$f.uvi-new" && mv -f "$2/$f.uvi-new" "$2/$f" || exit 1
+done || exit 1
+[ -f "$3" ] || exit 0
+# chown drops setuid/setgid, so the mode goes back on after it
+while IFS=: read -r -d '' uid gid mode f; do
+    chown -h "$uid:$gid" "$2/$f" || exit 1
+    [ -L "$2/$f" ] || chmod "$mode" "$2/$f" || exit 1
+done < "$3"
+"#;
+
+pub fn path(name: &str) -> PathBuf {
+    fetch_env("CACHE").join("staging").join(name)
+}
+
+// uid:gid:mode:path of everything in the staging dir that isn't root's, NUL separated
+fn owners_path(staging: &Path) -> PathBuf {
+    let mut owners = staging.as_os_str().to_owned();
+    owners.push(".owners");
+
+    PathBuf::from(owners)
+}
+
+// fresh, empty staging dir for `name`
+pub fn prepare(name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let staging = path(name);
+
+    if staging.exists() && fs::remove_dir_all(&staging).is_err() {
+        privilege::escalate("/", "rm", &["-rf", staging.to_str().unwrap()])?;
+    }
+
+    let _ = fs::remove_file(owners_path(&staging));
+
+    fs::create_dir_all(&staging)?;
+
+    Ok(staging)
+}
+
+// absolute paths of everything that isn't a directory, as they'll land on disk
+pub fn collect_files(staging: &Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut dirs = vec![staging.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let entry_path = entry.path();
+
+            match fs::symlink_metadata(&entry_path) {
+                Ok(meta) if meta.is_dir() => dirs.push(entry_path),
+                Ok(_) => {
+                    let relative = entry_path.strip_prefix(staging).unwrap();
+
+                    files.push(format!("/{}", relative.to_string_lossy()));
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    files.sort();
+    files
+}
+
+// writes down who package() made the staged files belong to. Under fakeroot that only
+// exists in its saved state, so find runs inside it to see the faked owners
+pub fn record_owners(
+    staging: &Path,
+    fakeroot_state: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut find = match fakeroot_state {
+        Some(state) => {
+            let mut fakeroot = Command::new("fakeroot");
+            fakeroot.arg("-i").arg(state).args(["--", "find"]);
+            fakeroot
+        }
+        None => Command::new("find"),
+    };
+
+    let output = find
+        .arg(staging)
+        .args(["-mindepth", "1", "-printf", "%U:%G:%m:%P\\0"])
+        .output()?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "can't read the owners of {}: {}",
+            staging.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+
+    let mut owners: Vec<u8> = vec![];
+
+    for entry in output.stdout.split(|byte| *byte == 0) {
+        if !entry.is_empty() && !entry.starts_with(b"0:0:") {
+            owners.extend(entry);
+            owners.push(0);
+        }
+    }
+
+    if !owners.is_empty() {
+        fs::write(owners_path(staging), owners)?;
+    }
+
+    Ok(())
+}
+
+pub fn merge(staging: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    println!(
+        "=> \x1b[33;1mTRY:\x1b[0m Merging {} into /..",
+        staging.to_string_lossy()
+    );
+
+    privilege::escalate(
+        "/",
+        "bash",
+        &[
+            "-c",
+            MERGE_SCRIPT,
+            "bash",
+            staging.to_str().unwrap(),
+            "/",
+            owners_path(staging).to_str().unwrap(),
+        ],
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    #[test]
+    fn owners_set_under_fakeroot_survive_the_merge() {
+        let dir = std::env::temp_dir().join(format!("uvi-staging-owners-{}", std::process::id()));
+        let (staging, root, state) = (dir.join("pkg"), dir.join("root"), dir.join("fakeroot"));
+
+        fs::create_dir_all(staging.join("var/lib/foo")).unwrap();
+        fs::create_dir_all(&root).unwrap();
+        fs::write(staging.join("var/lib/foo/helper"), "").unwrap();
+        fs::write(staging.join("var/lib/foo/plain"), "").unwrap();
+
+        let status = Command::new("fakeroot")
+            .arg("-s")
+            .arg(&state)
+            .args(["--", "sh", "-c"])
+            .arg("chown -R 123:456 var/lib/foo && chown 0:0 var/lib/foo/plain && chmod 4750 var/lib/foo/helper")
+            .current_dir(&staging)
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        record_owners(&staging, Some(&state)).unwrap();
+
+        let mut owners: Vec<String> = fs::read(owners_path(&staging))
+            .unwrap()
+            .split(|byte| *byte == 0)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| String::from_utf8_lossy(entry).to_string())
+            .collect();
+        owners.sort();
+
+        // root's files aren't listed, everything else is
+        assert_eq!(
+            owners,
+            ["123:456:4750:var/lib/foo/helper", "123:456:755:var/lib/foo"]
+        );
+
+        // only root can hand files to someone else
+        if privilege::is_root() {
+            let status = Command::new("bash")
+                .args(["-c", MERGE_SCRIPT, "bash"])
+                .arg(&staging)
+                .arg(&root)
+                .arg(owners_path(&staging))
+                .status()
+                .unwrap();
+
+            assert!(status.success());
+
+            let helper = fs::metadata(root.join("var/lib/foo/helper")).unwrap();
+
+            assert_eq!((helper.uid(), helper.gid()), (123, 456));
+            assert_eq!(helper.permissions().mode() & 0o7777, 0o4750);
+            assert_eq!(fs::metadata(root.join("var/lib/foo")).unwrap().uid(), 123);
+            assert_eq!(
+                fs::metadata(root.join("var/lib/foo/plain")).unwrap().uid(),
+                0
+            );
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_directories_keep_their_staged_mode() {
+        let dir = std::env::temp_dir().join(format!("uvi-staging-modes-{}", std::process::id()));
+        let (staging, root) = (dir.join("pkg"), dir.join("root"));
+
+        for (path, mode) in [
+            ("etc", 0o755),
+            ("etc/sudoers.d", 0o750),
+            ("var/spool/foo", 0o1777),
+            ("usr", 0o700),
+        ] {
+            fs::create_dir_all(staging.join(path)).unwrap();
+            fs::set_permissions(staging.join(path), fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        fs::write(staging.join("etc/sudoers.d/foo"), "").unwrap();
+        // already there, a package doesn't get to change it
+        fs::create_dir_all(root.join("usr")).unwrap();
+        fs::set_permissions(root.join("usr"), fs::Permissions::from_mode(0o755)).unwrap();
+
+        let status = Command::new("bash")
+            .args(["-c", MERGE_SCRIPT, "bash"])
+            .arg(&staging)
+            .arg(&root)
+            .arg(owners_path(&staging))
+            .status()
+            .unwrap();
+
+        assert!(status.success());
+
+        let mode =
+            |path: &str| fs::metadata(root.join(path)).unwrap().permissions().mode() & 0o7777;
+
+        assert_eq!(mode("etc/sudoers.d"), 0o750);
+        assert_eq!(mode("var/spool/foo"), 0o1777);
+        assert_eq!(mode("usr"), 0o755);
+        assert!(root.join("etc/sudoers.d/foo").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
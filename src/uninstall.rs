@@ -0,0 +1,253 @@
+use crate::{
+    database::{self, Package},
+    fetch_env, privilege,
+};
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+pub fn uninstall(name: &str, dry_run: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let pkg = match database::load(name) {
+        Some(pkg) => pkg,
+        None => return Err(format!("{name} is not installed").into()),
+    };
+
+    remove_files(&pkg, pkg.files.iter().collect(), dry_run)?;
+
+    if !dry_run {
+        database::remove(&pkg.name)?;
+
+        println!("=> \x1b[32;1mSUC:\x1b[0m Uninstalled {}!", pkg.name);
+    }
+
+    Ok(())
+}
+
+// an upgrade can drop files, whatever the old version had and the new one doesn't goes
+pub fn remove_stale(old: &Package, new: &Package) -> Result<(), Box<dyn std::error::Error>> {
+    let kept: HashSet<&String> = new.files.iter().collect();
+    let stale: Vec<&String> = old
+        .files
+        .iter()
+        .filter(|file| !kept.contains(file))
+        .collect();
+
+    remove_files(old, stale, false)
+}
+
+fn remove_files(
+    pkg: &Package,
+    mut files: Vec<&String>,
+    dry_run: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // files another package also claims stay where they are
+    let foreign: HashSet<String> = database::list()
+        .into_iter()
+        .filter(|other| other.name != pkg.name)
+        .flat_map(|other| other.files)
+        .collect();
+
+    let prefix = Path::new(if pkg.prefix.is_empty() {
+        "/"
+    } else {
+        pkg.prefix.as_str()
+    });
+
+    files.sort();
+    files.reverse();
+
+    let mut dirs: Vec<PathBuf> = vec![];
+    let mut denied: Vec<PathBuf> = vec![];
+
+    for file in files {
+        let path = Path::new(file);
+
+        if foreign.contains(file) {
+            println!("=> \x1b[31;1mERR:\x1b[0m {file} is owned by another package, keeping it");
+            continue;
+        }
+
+        if !path.exists() && !path.is_symlink() {
+            continue;
+        }
+
+        if dry_run {
+            println!("=> \x1b[1mINFO:\x1b[0m Would remove {file}");
+        } else if !remove_path(path)? {
+            denied.push(path.to_path_buf());
+        }
+
+        if let Some(parent) = path.parent() {
+            dirs.push(parent.to_path_buf());
+        }
+    }
+
+    if !dry_run {
+        escalate_removal("rm", &denied)?;
+        prune(dirs, prefix)?;
+    }
+
+    Ok(())
+}
+
+// walks up from every touched directory, removing the ones that ended up empty.
+// never goes above the top level dirs of the prefix (bin, lib, share..)
+fn prune(mut dirs: Vec<PathBuf>, prefix: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    dirs.sort();
+    dirs.dedup();
+
+    // dirs only root can remove, they count as gone when looking at their parent
+    let mut denied: Vec<PathBuf> = vec![];
+
+    while let Some(dir) = dirs.pop() {
+        if dir.parent() == Some(prefix) || !dir.starts_with(prefix) || dir == prefix {
+            continue;
+        }
+
+        let empty = match fs::read_dir(&dir) {
+            Ok(mut entries) => {
+                entries.all(|entry| entry.is_ok_and(|entry| denied.contains(&entry.path())))
+            }
+            Err(_) => false,
+        };
+
+        if empty {
+            if !remove_path(&dir)? {
+                denied.push(dir.clone());
+            }
+
+            if let Some(parent) = dir.parent() {
+                dirs.push(parent.to_path_buf());
+                dirs.sort();
+                dirs.dedup();
+            }
+        }
+    }
+
+    // children were found before their parents, so rmdir sees them in the right order
+    escalate_removal("rmdir", &denied)
+}
+
+// false if it takes root, the caller hands those to escalate_removal()
+fn remove_path(path: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+    let is_dir = path.is_dir() && !path.is_symlink();
+
+    let result = if is_dir {
+        fs::remove_dir(path)
+    } else {
+        fs::remove_file(path)
+    };
+
+    match result {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => return Ok(false),
+        Err(e) => return Err(e.into()),
+    }
+
+    println!("=> \x1b[1mINFO:\x1b[0m Removed {}", path.to_string_lossy());
+
+    Ok(true)
+}
+
+// one escalated call however many paths there are, so doas without persist asks once.
+// xargs reads them NUL separated from a list, a long one won't hit the argv limit
+fn escalate_removal(tool: &str, paths: &[PathBuf]) -> Result<(), Box<dyn std::error::Error>> {
+    if paths.is_empty() {
+        return Ok(());
+    }
+
+    let list = fetch_env("CACHE").join(format!("remove-{}.list", std::process::id()));
+    let mut content: Vec<u8> = vec![];
+
+    for path in paths {
+        content.extend(path.as_os_str().as_bytes());
+        content.push(0);
+    }
+
+    fs::create_dir_all(list.parent().unwrap())?;
+    fs::write(&list, content)?;
+
+    let result = privilege::escalate(
+        "/",
+        "xargs",
+        &["-0", "-a", list.to_str().unwrap(), tool, "--"],
+    );
+    let _ = fs::remove_file(&list);
+    result?;
+
+    for path in paths {
+        println!("=> \x1b[1mINFO:\x1b[0m Removed {}", path.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_removes_what_the_new_version_dropped() {
+        let prefix =
+            std::env::temp_dir().join(format!("uvi-uninstall-stale-{}", std::process::id()));
+        let kept = prefix.join("bin/foo");
+        let dropped = prefix.join("lib/foo/plugin.so");
+
+        fs::create_dir_all(kept.parent().unwrap()).unwrap();
+        fs::create_dir_all(dropped.parent().unwrap()).unwrap();
+        fs::write(&kept, "new").unwrap();
+        fs::write(&dropped, "old").unwrap();
+
+        let package = |files: &[&Path]| Package {
+            name: format!("uvi-test-stale-{}", std::process::id()),
+            prefix: prefix.to_string_lossy().to_string(),
+            files: files
+                .iter()
+                .map(|file| file.to_string_lossy().to_string())
+                .collect(),
+            ..Default::default()
+        };
+
+        remove_stale(&package(&[&kept, &dropped]), &package(&[&kept])).unwrap();
+
+        assert!(kept.exists());
+        assert!(!dropped.exists());
+        // emptied dirs go, the prefix's top level ones stay
+        assert!(!prefix.join("lib/foo").exists());
+        assert!(prefix.join("lib").exists());
+
+        fs::remove_dir_all(&prefix).unwrap();
+    }
+
+    #[test]
+    fn escalated_removal_takes_a_list() {
+        // escalating means sudo when not root, nothing a test should run
+        if !privilege::is_root() {
+            return;
+        }
+
+        let dir =
+            std::env::temp_dir().join(format!("uvi-uninstall-escalate-{}", std::process::id()));
+        let files = [
+            dir.join("plain"),
+            dir.join("with space"),
+            dir.join("new\nline"),
+        ];
+
+        fs::create_dir_all(dir.join("empty")).unwrap();
+        for file in &files {
+            fs::write(file, "").unwrap();
+        }
+
+        escalate_removal("rm", &files).unwrap();
+        escalate_removal("rmdir", &[dir.join("empty")]).unwrap();
+
+        assert!(fs::read_dir(&dir).unwrap().next().is_none());
+
+        fs::remove_dir(&dir).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+// --upgrade: refetches the source every installed package came from, compares versions
+// and rebuilds whatever has something newer, dependencies first. A .deb or .rpm is
+// downloaded again from its url (or reread from its path) and reinstalled if the one
+// there now is newer, which only finds something for urls that follow the latest release.
+
+use crate::{
+    InstallOptions,
+    archive::{self, Format},
+    clone_strategy,
+    compilers::pkgbuild,
+    database::{self, Package},
+    deb,
+    dependencies::{self, Build},
+    fetch, fetch_env, git, git_version, install_with_dependencies, merge_staged, rpm,
+    version::Version,
+};
+use rprompt::prompt_reply;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+struct Candidate {
+    build: Build,
+    // every installed package built from this source, split packages share one
+    installed: Vec<Package>,
+    available: String,
+    // set for .deb and .rpm packages, `build.dir` is then the package file
+    prebuilt: Option<Format>,
+}
+
+pub fn upgrade(
+    target: Option<&str>,
+    repo: &str,
+    options: &InstallOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let installed: Vec<Package> = match target {
+        Some(name) => match database::load(name) {
+            Some(pkg) => vec![pkg],
+            None => return Err(format!("{name} is not installed").into()),
+        },
+        None => database::list(),
+    };
+
+    if installed.is_empty() {
+        println!("=> \x1b[1mINFO:\x1b[0m Nothing installed by uvi yet..");
+        return Ok(());
+    }
+
+    let mut by_source: BTreeMap<String, Vec<Package>> = BTreeMap::new();
+
+    for pkg in installed {
+        by_source.entry(pkg.source.clone()).or_default().push(pkg);
+    }
+
+    let mut candidates: Vec<Candidate> = vec![];
+
+    for (source, packages) in by_source {
+        if let Some(format) = prebuilt_format(&packages[0]) {
+            println!("=> \x1b[33;1mTRY:\x1b[0m Checking {source}..");
+
+            let (file, available) = match prebuilt_version(&source, format) {
+                Ok(found) => found,
+                Err(e) => {
+                    println!("=> \x1b[31;1mERR:\x1b[0m {e}, skipping..");
+                    continue;
+                }
+            };
+
+            let outdated = packages
+                .iter()
+                .any(|pkg| Version::from(available.as_str()) > Version::from(pkg.version.as_str()));
+
+            if outdated {
+                candidates.push(Candidate {
+                    build: Build {
+                        name: packages[0].name.clone(),
+                        dir: file,
+                        source,
+                    },
+                    installed: packages,
+                    available,
+                    prebuilt: Some(format),
+                });
+            }
+
+            continue;
+        }
+
+        if !git::GitUrl::parse(&source).url.ends_with(".git") {
+            println!(
+                "=> \x1b[1mINFO:\x1b[0m Can't check {} for upgrades, source is {:?}",
+                packages[0].name, source
+            );
+            continue;
+        }
+
+        println!("=> \x1b[33;1mTRY:\x1b[0m Checking {source}..");
+
+        let dir = fetch_env("CACHE").join(checkout_name(&source, &packages[0]));
+
+        let dir = match git::checkout(
+            &source,
+            &dir,
+            clone_strategy(&source),
+            options.pkgbuild.gpg_home.as_deref(),
+        ) {
+            Ok(dir) => dir,
+            Err(e) => {
+                println!("=> \x1b[31;1mERR:\x1b[0m {e}, skipping..");
+                continue;
+            }
+        };
+
+        let available = available_version(&dir)?;
+
+        let outdated = packages.iter().any(|pkg| {
+            if pkg.build_system == "pkgbuild" {
+                Version::from(available.as_str()) > Version::from(pkg.version.as_str())
+            } else {
+                // git describe output only says whether upstream moved, not by how much
+                available != pkg.version
+            }
+        });
+
+        if outdated {
+            candidates.push(Candidate {
+                build: Build {
+                    name: packages[0].name.clone(),
+                    dir,
+                    source,
+                },
+                installed: packages,
+                available,
+                prebuilt: None,
+            });
+        }
+    }
+
+    if candidates.is_empty() {
+        println!("=> \x1b[32;1mSUC:\x1b[0m Everything is up to date!");
+        return Ok(());
+    }
+
+    print_table(&candidates);
+
+    if !options.fast {
+        let reply = prompt_reply("=> \x1b[33;1mTRY:\x1b[0m Proceed with upgrade? [Y/n]: ")?;
+
+        if reply == "n" {
+            return Ok(());
+        }
+    }
+
+    let (prebuilt, built): (Vec<Candidate>, Vec<Candidate>) = candidates
+        .into_iter()
+        .partition(|candidate| candidate.prebuilt.is_some());
+
+    for candidate in prebuilt {
+        let Build {
+            dir: file, source, ..
+        } = candidate.build;
+
+        if candidate.prebuilt == Some(Format::Deb) {
+            let pkg = deb::stage(&file, options.nodeps, options.fast)?;
+
+            merge_staged(vec![pkg], &source, options)?;
+        } else {
+            rpm::install(&file, &source, options)?;
+        }
+    }
+
+    let builds = built.into_iter().map(|candidate| candidate.build).collect();
+
+    for build in dependencies::sort(builds, options.pkgbuild.nocheck)? {
+        let options = rebuild_options(options, database::load(&build.name));
+
+        install_with_dependencies(&build.dir, &build.source, repo, &options)?;
+    }
+
+    Ok(())
+}
+
+// rebuilt into the prefix and with the --bargs it was installed with, not today's
+fn rebuild_options(options: &InstallOptions, recorded: Option<Package>) -> InstallOptions {
+    match recorded {
+        Some(pkg) if !pkg.prefix.is_empty() => InstallOptions {
+            prefix: pkg.prefix,
+            bargs: pkg.bargs,
+            ..options.clone()
+        },
+        _ => options.clone(),
+    }
+}
+
+// meson and make packages are named after their checkout, PKGBUILD ones after the repo
+fn checkout_name(source: &str, pkg: &Package) -> String {
+    if pkg.build_system == "pkgbuild" {
+        let url = git::GitUrl::parse(source).url;
+        let base = url.rsplit('/').next().unwrap_or(&url);
+
+        base.trim_end_matches(".git").to_string()
+    } else {
+        pkg.name.clone()
+    }
+}
+
+fn prebuilt_format(pkg: &Package) -> Option<Format> {
+    match pkg.build_system.as_str() {
+        "deb" => Some(Format::Deb),
+        "rpm" => Some(Format::Rpm),
+        _ => None,
+    }
+}
+
+// a package installed from a path is reread where it is, one from a url is downloaded again
+fn prebuilt_version(
+    source: &str,
+    format: Format,
+) -> Result<(PathBuf, String), Box<dyn std::error::Error>> {
+    let file = if Path::new(source).exists() {
+        PathBuf::from(source)
+    } else if source.starts_with("http://") || source.starts_with("https://") {
+        let filename = source.rsplit('/').next().unwrap_or("download.tmp");
+        let file = fetch_env("CACHE").join(filename);
+
+        fetch(source, &file)?;
+        file
+    } else {
+        return Err(format!("{source} is gone").into());
+    };
+
+    let version = if format == Format::Deb {
+        deb::control(&file)?.version
+    } else {
+        rpm::version(&archive::rpm::open(&file)?.0)
+    };
+
+    Ok((file, version))
+}
+
+fn available_version(dir: &Path) -> Result<String, Box<dyn std::error::Error>> {
+    let pkgbuild_path = dir.join("PKGBUILD");
+
+    if pkgbuild_path.exists() {
+        Ok(pkgbuild::full_version(&pkgbuild::load(&pkgbuild_path)?))
+    } else {
+        Ok(git_version(dir))
+    }
+}
+
+fn print_table(candidates: &[Candidate]) {
+    let rows: Vec<(&str, &str, &str)> = candidates
+        .iter()
+        .flat_map(|candidate| {
+            candidate.installed.iter().map(|pkg| {
+                (
+                    pkg.name.as_str(),
+                    pkg.version.as_str(),
+                    candidate.available.as_str(),
+                )
+            })
+        })
+        .collect();
+
+    let name_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0).max(7);
+    let version_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0).max(9);
+
+    println!(
+        "\n    \x1b[1m{:name_width$}  {:version_width$}  available\x1b[0m",
+        "package", "installed"
+    );
+
+    for (name, installed, available) in rows {
+        println!("    {name:name_width$}  {installed:version_width$}  {available}");
+    }
+
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebuilds_keep_the_recorded_prefix_and_bargs() {
+        let options = InstallOptions {
+            prefix: "/usr".to_string(),
+            bargs: "-Dtoday=true".to_string(),
+            build: true,
+            ..Default::default()
+        };
+        let recorded = Package {
+            name: "foo".to_string(),
+            prefix: "/opt/x".to_string(),
+            bargs: "-Dwayland=false".to_string(),
+            ..Default::default()
+        };
+
+        let rebuild = rebuild_options(&options, Some(recorded));
+
+        assert_eq!(rebuild.prefix, "/opt/x");
+        assert_eq!(rebuild.bargs, "-Dwayland=false");
+        assert!(rebuild.build);
+
+        // nothing recorded to go by, so this invocation's
+        assert_eq!(rebuild_options(&options, None).prefix, "/usr");
+    }
+}
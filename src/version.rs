@@ -0,0 +1,282 @@
+// epoch:pkgver-pkgrel, ordered the way pacman's vercmp orders them.
+// Like pacman (and unlike rpm) a `~` is just another separator.
+
+use std::{cmp::Ordering, fmt};
+
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub epoch: String,
+    pub pkgver: String,
+    pub pkgrel: Option<String>,
+}
+
+impl Version {
+    pub fn parse(version: &str) -> Version {
+        // an epoch is only digits followed by `:`, anything else belongs to pkgver
+        let digits = version.len()
+            - version
+                .trim_start_matches(|c: char| c.is_ascii_digit())
+                .len();
+
+        let (epoch, rest) = match version[digits..].strip_prefix(':') {
+            Some(rest) if digits > 0 => (&version[..digits], rest),
+            Some(rest) => ("0", rest),
+            None => ("0", version),
+        };
+
+        let (pkgver, pkgrel) = match rest.rsplit_once('-') {
+            Some((pkgver, pkgrel)) => (pkgver, Some(pkgrel.to_string())),
+            None => (rest, None),
+        };
+
+        Version {
+            epoch: epoch.to_string(),
+            pkgver: pkgver.to_string(),
+            pkgrel,
+        }
+    }
+}
+
+impl From<&str> for Version {
+    fn from(version: &str) -> Version {
+        Version::parse(version)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.epoch != "0" {
+            write!(f, "{}:", self.epoch)?;
+        }
+
+        write!(f, "{}", self.pkgver)?;
+
+        if let Some(pkgrel) = &self.pkgrel {
+            write!(f, "-{pkgrel}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// the pkgrel only counts when both sides have one, so 1.5 == 1.5-1 and 1.5 == 1.5-2
+// even though 1.5-1 < 1.5-2, same as vercmp
+impl Ord for Version {
+    fn cmp(&self, other: &Version) -> Ordering {
+        segment_cmp(&self.epoch, &other.epoch)
+            .then_with(|| segment_cmp(&self.pkgver, &other.pkgver))
+            .then_with(|| match (&self.pkgrel, &other.pkgrel) {
+                (Some(a), Some(b)) => segment_cmp(a, b),
+                _ => Ordering::Equal,
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Version) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Version) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+// rpmvercmp as pacman has it: walk alternating runs of digits and letters, numbers
+// compare as numbers, letters as text, and a number always beats letters
+fn segment_cmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut one, mut two) = (0, 0);
+
+    while one < a.len() && two < b.len() {
+        let (start_one, start_two) = (one, two);
+
+        while one < a.len() && !a[one].is_ascii_alphanumeric() {
+            one += 1;
+        }
+        while two < b.len() && !b[two].is_ascii_alphanumeric() {
+            two += 1;
+        }
+
+        if one == a.len() || two == b.len() {
+            break;
+        }
+
+        // 2___a > 2_a
+        if one - start_one != two - start_two {
+            return (one - start_one).cmp(&(two - start_two));
+        }
+
+        let numeric = a[one].is_ascii_digit();
+        let same_kind = |c: &u8| {
+            if numeric {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+
+        let end_one = one + a[one..].iter().take_while(|c| same_kind(c)).count();
+        let end_two = two + b[two..].iter().take_while(|c| same_kind(c)).count();
+
+        // 1.5.1 > 1.5.b but 1.5.a < 1.5.1
+        if end_two == two {
+            return if numeric {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let (mut x, mut y) = (&a[one..end_one], &b[two..end_two]);
+
+        if numeric {
+            while x.len() > 1 && x[0] == b'0' {
+                x = &x[1..];
+            }
+            while y.len() > 1 && y[0] == b'0' {
+                y = &y[1..];
+            }
+
+            if x.len() != y.len() {
+                return x.len().cmp(&y.len());
+            }
+        }
+
+        let ordering = x.cmp(y);
+
+        if ordering.is_ne() {
+            return ordering;
+        }
+
+        one = end_one;
+        two = end_two;
+    }
+
+    let (rest_one, rest_two) = (a.get(one), b.get(two));
+
+    if rest_one.is_none() && rest_two.is_none() {
+        return Ordering::Equal;
+    }
+
+    // whatever is left over decides it, but trailing letters never beat nothing (1.0rc < 1.0)
+    if (rest_one.is_none() && !rest_two.is_some_and(|c| c.is_ascii_alphabetic()))
+        || rest_one.is_some_and(|c| c.is_ascii_alphabetic())
+    {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // pacman's test/util/vercmptest.sh, each row is checked both ways round
+    const VERCMP: &[(&str, &str, Ordering)] = &[
+        // all similar length, no pkgrel
+        ("1.5.0", "1.5.0", Ordering::Equal),
+        ("1.5.1", "1.5.0", Ordering::Greater),
+        // mixed length
+        ("1.5.1", "1.5", Ordering::Greater),
+        // with pkgrel, simple
+        ("1.5.0-1", "1.5.0-1", Ordering::Equal),
+        ("1.5.0-1", "1.5.0-2", Ordering::Less),
+        ("1.5.0-1", "1.5.1-1", Ordering::Less),
+        ("1.5.0-2", "1.5.1-1", Ordering::Less),
+        // with pkgrel, mixed lengths
+        ("1.5-1", "1.5.1-1", Ordering::Less),
+        ("1.5-2", "1.5.1-1", Ordering::Less),
+        ("1.5-2", "1.5.1-2", Ordering::Less),
+        // mixed pkgrel inclusion
+        ("1.5", "1.5-1", Ordering::Equal),
+        ("1.5-1", "1.5", Ordering::Equal),
+        ("1.1-1", "1.1", Ordering::Equal),
+        ("1.0-1", "1.1", Ordering::Less),
+        ("1.1-1", "1.0", Ordering::Greater),
+        // alphanumeric versions
+        ("1.5b-1", "1.5-1", Ordering::Less),
+        ("1.5b", "1.5", Ordering::Less),
+        ("1.5b-1", "1.5", Ordering::Less),
+        ("1.5b", "1.5.1", Ordering::Less),
+        // from the manpage
+        ("1.0a", "1.0alpha", Ordering::Less),
+        ("1.0alpha", "1.0b", Ordering::Less),
+        ("1.0b", "1.0beta", Ordering::Less),
+        ("1.0beta", "1.0rc", Ordering::Less),
+        ("1.0rc", "1.0", Ordering::Less),
+        // alpha-dotted versions
+        ("1.5.a", "1.5", Ordering::Greater),
+        ("1.5.b", "1.5.a", Ordering::Greater),
+        ("1.5.1", "1.5.b", Ordering::Greater),
+        // alpha dots and dashes
+        ("1.5.b-1", "1.5.b", Ordering::Equal),
+        ("1.5-1", "1.5.b", Ordering::Less),
+        // same/similar content, differing separators
+        ("2.0", "2_0", Ordering::Equal),
+        ("2.0_a", "2_0.a", Ordering::Equal),
+        ("2.0a", "2.0.a", Ordering::Less),
+        ("2___a", "2_a", Ordering::Greater),
+        // epoch included version comparisons
+        ("0:1.0", "0:1.0", Ordering::Equal),
+        ("0:1.0", "0:1.1", Ordering::Less),
+        ("1:1.0", "0:1.0", Ordering::Greater),
+        ("1:1.0", "0:1.1", Ordering::Greater),
+        ("1:1.0", "2:1.1", Ordering::Less),
+        // epoch + sometimes present pkgrel
+        ("1:1.0", "0:1.0-1", Ordering::Greater),
+        ("1:1.0-1", "0:1.1-1", Ordering::Greater),
+        // epoch included on one version
+        ("0:1.0", "1.0", Ordering::Equal),
+        ("0:1.0", "1.1", Ordering::Less),
+        ("0:1.1", "1.0", Ordering::Greater),
+        ("1:1.0", "1.0", Ordering::Greater),
+        ("1:1.0", "1.1", Ordering::Greater),
+        ("1:1.1", "1.1", Ordering::Greater),
+        // leading zeroes and long numbers
+        ("1.05", "1.5", Ordering::Equal),
+        ("1.0010", "1.9", Ordering::Greater),
+        ("20240101", "9999", Ordering::Greater),
+        // a tilde is a plain separator, not rpm's "sorts before anything"
+        ("1.0~rc1", "1.0", Ordering::Greater),
+        ("1.0~rc1", "1.0.rc1", Ordering::Equal),
+        ("1.0~rc1", "1.0rc1", Ordering::Greater),
+        ("1.0~~rc1", "1.0~rc1", Ordering::Greater),
+    ];
+
+    #[test]
+    fn vercmp_table() {
+        for (a, b, expected) in VERCMP {
+            let (one, two) = (Version::parse(a), Version::parse(b));
+
+            assert_eq!(one.cmp(&two), *expected, "vercmp {a} {b}");
+            assert_eq!(two.cmp(&one), expected.reverse(), "vercmp {b} {a}");
+        }
+    }
+
+    #[test]
+    fn parse_splits_epoch_and_pkgrel() {
+        let version = Version::parse("2:1.0.3-4");
+
+        assert_eq!(version.epoch, "2");
+        assert_eq!(version.pkgver, "1.0.3");
+        assert_eq!(version.pkgrel.as_deref(), Some("4"));
+        assert_eq!(version.to_string(), "2:1.0.3-4");
+
+        // only digits before the colon make an epoch
+        let version = Version::parse("a:1.0");
+
+        assert_eq!(version.epoch, "0");
+        assert_eq!(version.pkgver, "a:1.0");
+        assert_eq!(version.pkgrel, None);
+    }
+}